@@ -0,0 +1,947 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    constants::{DEFAULT_NOTION_HOSTS, JOURNAL_DIR_NAME, PLACEHOLDER_PAGE_NAME, UNDO_RECORD_FILE_NAME},
+    content_replacing::{self, ExternalLinks, ReferencesMatcher, RenameRefsInFileError, RenamedContents},
+    error::{CleanError, DiscoveryError, PlanningError, RenameError, RewriteError},
+    export_archive::ExportArchive,
+    export_source::{ExportDirectory, ExportSource},
+    file_type::{self, FileMapByName, FileType},
+    journal::Journal,
+    link_checking::{self, VerifyReport},
+    manifest::NameManifest,
+    naming::{self, NameTemplate, NamingOptions, SanitizeNames},
+    notion_object::{NotionObject, ObjectsMapByName},
+    path_replacing,
+    plan::RenamePlan,
+    tree_writing,
+    undo::UndoRecord,
+};
+
+/// Cleaner is the entry point to clean a Notion export, either a directory or the zip file exported by Notion.
+///
+/// ```
+/// use notion_export_cleaner::Cleaner;
+/// # use std::fs;
+/// # let export = tempfile::tempdir().unwrap();
+/// # let output = tempfile::tempdir().unwrap();
+/// # fs::create_dir(export.path().join(".git")).unwrap();
+/// # fs::write(export.path().join("Home 0123456789abcdef0123456789abcdef.md"), "# Home\n").unwrap();
+/// # let (export_dir, output_dir) = (export.path(), output.path().join("cleaned"));
+///
+/// let report = Cleaner::new(export_dir)
+///     .output_dir(&output_dir)
+///     .ignore([".git"])
+///     .verify(true)
+///     .run()?;
+/// assert_eq!(report.file_counts.markdown, 1);
+/// assert_eq!(report.objects_count, 1);
+/// assert!(report.errors.is_empty());
+/// assert!(!report.verify.unwrap().has_problems());
+/// assert!(output_dir.join("Home.md").exists());
+/// # Ok::<(), notion_export_cleaner::error::CleanError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Cleaner {
+    input: PathBuf,
+    output_dir: Option<PathBuf>,
+    ignore: Vec<String>,
+    manifest_path: Option<PathBuf>,
+    with_plan: bool,
+    notion_hosts: Vec<String>,
+    external_links: ExternalLinks,
+    naming: NamingOptions,
+    verify: bool,
+    dry_run: bool,
+}
+
+/// How many entries of each type were found in the export.
+#[derive(Debug, Default, Clone)]
+pub struct FileCounts {
+    pub markdown: usize,
+    pub html: usize,
+    pub csv: usize,
+    pub csv_all: usize,
+    pub directories: usize,
+    pub other_text: usize,
+    pub other_binary: usize,
+}
+
+/// A non-fatal problem found in a file while renaming its references.
+#[derive(Debug, Clone)]
+pub struct FileWarning {
+    pub path: PathBuf,
+    pub error: RenameRefsInFileError,
+}
+
+/// What a cleaning did.
+#[derive(Debug)]
+pub struct CleanReport {
+    pub file_counts: FileCounts,
+    /// Number of pages and databases found, which are renamed.
+    pub objects_count: usize,
+    /// Number of pages and databases already renamed by a previous run, which are left as they are.
+    pub cleaned_objects_count: usize,
+    pub warnings: Vec<FileWarning>,
+    /// Errors of the files that were skipped. The rest of the export is cleaned.
+    pub errors: Vec<CleanError>,
+    /// The plan of all renames and replacements, if it was asked for with `Cleaner::plan`.
+    pub plan: Option<RenamePlan>,
+    /// The verification of the links of the cleaned export, if it was asked for with `Cleaner::verify`.
+    pub verify: Option<VerifyReport>,
+}
+
+/// What an undo did.
+#[derive(Debug)]
+pub struct UndoReport {
+    /// Files modified since they were cleaned: they got their name back, but not their references.
+    pub modified_files: Vec<PathBuf>,
+}
+
+impl Cleaner {
+    /// `input` is either a directory, or the zip file exported by Notion.
+    pub fn new(input: impl Into<PathBuf>) -> Self {
+        Self {
+            input: input.into(),
+            output_dir: None,
+            ignore: vec![],
+            manifest_path: None,
+            with_plan: false,
+            notion_hosts: DEFAULT_NOTION_HOSTS.iter().map(|host| host.to_string()).collect(),
+            external_links: ExternalLinks::default(),
+            naming: NamingOptions::default(),
+            verify: false,
+            dry_run: false,
+        }
+    }
+
+    /// Writes the cleaned export to this directory instead of modifying the input in place.
+    /// The input is left untouched. The directory must not exist or be empty.
+    /// For a zip input, it defaults to the path of the zip file without its extension.
+    pub fn output_dir(mut self, output_dir: impl Into<PathBuf>) -> Self {
+        self.output_dir = Some(output_dir.into());
+        self
+    }
+
+    /// Names of the directories and files to leave untouched, like `.git`.
+    pub fn ignore<S: Into<String>>(mut self, names: impl IntoIterator<Item = S>) -> Self {
+        self.ignore.extend(names.into_iter().map(Into::into));
+        self
+    }
+
+    /// Reads the names chosen in a previous run from this file, and writes the names chosen in this run to it.
+    /// See `NameManifest`.
+    pub fn manifest(mut self, manifest_path: impl Into<PathBuf>) -> Self {
+        self.manifest_path = Some(manifest_path.into());
+        self
+    }
+
+    /// Adds the plan of all renames and replacements to the report.
+    pub fn plan(mut self, with_plan: bool) -> Self {
+        self.with_plan = with_plan;
+        self
+    }
+
+    /// Hosts of links to Notion pages, in addition to `notion.so` and `notion.site`, like the custom domain of a workspace.
+    /// Each host matches its subdomains too.
+    pub fn notion_hosts<S: Into<String>>(mut self, hosts: impl IntoIterator<Item = S>) -> Self {
+        self.notion_hosts.extend(hosts.into_iter().map(Into::into));
+        self
+    }
+
+    /// What to do with the links to Notion pages that are not part of the export. They are reported as warnings in any case.
+    pub fn external_links(mut self, external_links: ExternalLinks) -> Self {
+        self.external_links = external_links;
+        self
+    }
+
+    /// What to do with the characters of the names of the pages that some file systems don't accept.
+    pub fn sanitize_names(mut self, sanitize_names: SanitizeNames) -> Self {
+        self.naming.sanitize_names = sanitize_names;
+        self
+    }
+
+    /// What the new names of the pages and databases are made of, like `{name} ({short_uuid})`, see `NameTemplate`.
+    pub fn name_template(mut self, name_template: NameTemplate) -> Self {
+        self.naming.name_template = name_template;
+        self
+    }
+
+    /// Truncates the names of the pages and databases whose file names would be longer than `max_name_bytes` bytes, extension included.
+    pub fn max_name_bytes(mut self, max_name_bytes: usize) -> Self {
+        self.naming.max_name_bytes = Some(max_name_bytes);
+        self
+    }
+
+    /// Truncates the names of the pages and databases whose paths, relative to the root of the export,
+    /// would be longer than `max_path_bytes` bytes once their parent directories are renamed.
+    pub fn max_path_bytes(mut self, max_path_bytes: usize) -> Self {
+        self.naming.max_path_bytes = Some(max_path_bytes);
+        self
+    }
+
+    /// Compares names ignoring their case and their Unicode normalization to find collisions, like macOS and Windows do.
+    /// Pages named `Notes` and `notes` in the same directory then get different names: `Notes` and `notes 1`.
+    pub fn insensitive_collisions(mut self, insensitive_collisions: bool) -> Self {
+        self.naming.insensitive_collisions = insensitive_collisions;
+        self
+    }
+
+    /// Once the export is cleaned, checks that its relative links point to existing files, and adds the result to the report.
+    /// See `link_checking::verify_links`. Nothing is checked with `dry_run`, since nothing is written.
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    /// Executes everything except the actual writing to files.
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    pub fn is_zip_input(&self) -> bool {
+        self.input.is_file()
+            && self
+                .input
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"))
+    }
+
+    /// Where the cleaned export is written. None if the input is modified in place.
+    pub fn get_output_dir(&self) -> Option<PathBuf> {
+        match &self.output_dir {
+            Some(output_dir) => Some(output_dir.clone()),
+            None if self.is_zip_input() => Some(self.input.with_extension("")),
+            None => None,
+        }
+    }
+
+    /// Cleans the export.
+    /// Files that can't be read, written or made into pages are skipped and listed in the report.
+    /// Other errors stop the cleaning.
+    pub fn run(&self) -> Result<CleanReport, CleanError> {
+        let ignore = self.get_ignored_names();
+
+        let mut report = self.clean(&ignore)?;
+        if self.verify && !self.dry_run {
+            let cleaned_dir = self.get_output_dir().unwrap_or_else(|| self.input.clone());
+            report.verify = Some(link_checking::verify_links(&cleaned_dir, &ignore, &self.notion_hosts));
+        }
+        Ok(report)
+    }
+
+    /// Cleans the export in place, or to the output directory, leaving out the entries in `ignore`.
+    fn clean(&self, ignore: &HashSet<&str>) -> Result<CleanReport, CleanError> {
+        if !self.input.exists() {
+            return Err(DiscoveryError::InputNotFound(self.input.clone()).into());
+        }
+
+        if self.is_zip_input() {
+            let output_dir = self.get_output_dir().unwrap(); // Should not panic, there is always an output dir for zips
+            check_output_dir_is_usable(&output_dir)?;
+
+            let archive = ExportArchive::open(&self.input)
+                .map_err(|source| DiscoveryError::Archive { path: self.input.clone(), source })?;
+            let (file_map, errors) = file_type::build_file_map_from_archive(&archive, ignore);
+            return self.clean_to(&archive, &file_map, errors, &output_dir);
+        }
+
+        if self.has_interrupted_run()? {
+            return Err(CleanError::InterruptedRun(self.input.clone()));
+        }
+
+        match &self.output_dir {
+            Some(output_dir) => {
+                check_output_dir_is_usable(output_dir)?;
+                let canonical_input = self.input.canonicalize()
+                    .map_err(|source| CleanError::Io { path: self.input.clone(), source })?;
+                let canonical_output_dir = canonicalize_missing_path(output_dir)
+                    .map_err(|source| CleanError::Io { path: output_dir.clone(), source })?;
+                if canonical_output_dir.starts_with(&canonical_input) {
+                    return Err(CleanError::OutputDirInInput {
+                        output_dir: output_dir.clone(),
+                        input_dir: self.input.clone(),
+                    });
+                }
+
+                let source = ExportDirectory::new(self.input.clone());
+                let (file_map, errors) = file_type::build_file_map(&self.input, ignore);
+                self.clean_to(&source, &file_map, errors, output_dir)
+            }
+            None => {
+                let (file_map, errors) = file_type::build_file_map(&self.input, ignore);
+                self.clean_in_place(&file_map, errors)
+            }
+        }
+    }
+
+    /// Is there a run that was interrupted while modifying the input directory in place?
+    /// A run interrupted before modifying anything is not considered, see `discard_uncommitted_journal`.
+    pub fn has_interrupted_run(&self) -> Result<bool, CleanError> {
+        if !Journal::exists(&self.input) {
+            return Ok(false);
+        }
+
+        Ok(self.open_journal()?.is_committed())
+    }
+
+    /// Finishes the run that was interrupted while modifying the input directory in place.
+    /// Returns the number of operations of this run.
+    pub fn resume_interrupted_run(&self) -> Result<usize, CleanError> {
+        if !self.has_interrupted_run()? {
+            return Err(CleanError::NoInterruptedRun(self.input.clone()));
+        }
+
+        let journal = self.open_journal()?;
+        let operations_count = journal.operations_count();
+        journal.resume().map_err(RenameError::Resume)?;
+        Ok(operations_count)
+    }
+
+    /// Undoes the modifications of the run that was interrupted while modifying the input directory in place.
+    /// Returns the number of operations of this run.
+    pub fn rollback_interrupted_run(&self) -> Result<usize, CleanError> {
+        if !self.has_interrupted_run()? {
+            return Err(CleanError::NoInterruptedRun(self.input.clone()));
+        }
+
+        let journal = self.open_journal()?;
+        let operations_count = journal.operations_count();
+        journal.rollback().map_err(RenameError::Rollback)?;
+        Ok(operations_count)
+    }
+
+    /// Checks the links of the input directory as it is, without cleaning it. It's typically an export already cleaned.
+    /// See `link_checking::verify_links`.
+    pub fn verify_links(&self) -> Result<VerifyReport, CleanError> {
+        if !self.input.exists() {
+            return Err(DiscoveryError::InputNotFound(self.input.clone()).into());
+        }
+        if !self.input.is_dir() {
+            return Err(CleanError::NotADirectory(self.input.clone()));
+        }
+
+        Ok(link_checking::verify_links(&self.input, &self.get_ignored_names(), &self.notion_hosts))
+    }
+
+    /// Restores the original export from a cleaned one, in place, using the `UndoRecord` left by the cleaning.
+    /// The runs are undone one after the other, from the last one, each with its own journal:
+    /// once a run is undone, the record left is the one of the run before it.
+    /// With `dry_run`, only the last run is checked, since the previous ones depend on it being undone.
+    pub fn undo(&self) -> Result<UndoReport, CleanError> {
+        if !UndoRecord::exists(&self.input) {
+            return Err(CleanError::NotCleaned(self.input.clone()));
+        }
+        if self.has_interrupted_run()? {
+            return Err(CleanError::InterruptedRun(self.input.clone()));
+        }
+
+        let undo_record_path = self.input.join(UNDO_RECORD_FILE_NAME);
+        let mut undo_record = UndoRecord::read(&self.input)
+            .map_err(|source| CleanError::Io { path: undo_record_path.clone(), source })?;
+
+        if self.dry_run {
+            let modified_files = undo_record.stage_undo(None).map_err(RenameError::StageUndo)?;
+            return Ok(UndoReport { modified_files });
+        }
+
+        self.discard_uncommitted_journal()?;
+        let mut modified_files = vec![];
+        loop {
+            let mut journal = Journal::create(&self.input).map_err(RenameError::CreateJournal)?;
+            modified_files.extend(undo_record.stage_undo(Some(&mut journal)).map_err(RenameError::StageUndo)?);
+
+            journal.apply().map_err(RenameError::Apply)?;
+
+            match undo_record.previous.take() {
+                Some(previous) => {
+                    previous.write(&self.input)
+                        .map_err(|source| CleanError::Io { path: undo_record_path.clone(), source })?;
+                    undo_record = *previous;
+                }
+                None => {
+                    undo_record.remove().map_err(|source| CleanError::Io { path: undo_record_path, source })?;
+                    break;
+                }
+            }
+        }
+
+        Ok(UndoReport { modified_files })
+    }
+
+    /// Keeps the record of the previous run on the input directory, if any, in `undo_record`,
+    /// so that undoing this run can be followed by undoing the previous one.
+    fn chain_previous_undo_record(&self, undo_record: &mut UndoRecord) -> Result<(), CleanError> {
+        if self.input.is_dir() && UndoRecord::exists(&self.input) {
+            let previous = UndoRecord::read(&self.input)
+                .map_err(|source| CleanError::Io { path: self.input.join(UNDO_RECORD_FILE_NAME), source })?;
+            undo_record.previous = Some(Box::new(previous));
+        }
+        Ok(())
+    }
+
+    /// Cleans the input directory in place.
+    /// All modifications are staged in a journal before being applied, see `Journal`.
+    fn clean_in_place(&self, file_map: &FileMapByName, discovery_errors: Vec<DiscoveryError>) -> Result<CleanReport, CleanError> {
+        let files_vec = file_map.values().flatten().collect::<Vec<&FileType>>();
+
+        // Only used to list the entries already on disk, which must not be overwritten
+        let mut existing_paths = ExportDirectory::new(self.input.clone()).get_all_paths();
+        let (placeholder_page, placeholder_page_exists) =
+            self.reserve_placeholder_page(&self.input, file_map, &mut existing_paths, |path| fs::read_to_string(path));
+        let (objects_map, manifest, planning_errors) = self.build_objects_map(file_map, &self.input, &existing_paths, |path| fs::read_to_string(path))?;
+        let objects_vec = objects_map
+            .values()
+            .flatten()
+            .collect::<Vec<&NotionObject>>();
+
+        let mut recorder = RunRecorder::new(&self.input, &objects_vec, self.with_plan);
+        recorder.add_errors(discovery_errors);
+        recorder.add_errors(planning_errors);
+        let journal = if self.dry_run {
+            None
+        } else {
+            self.discard_uncommitted_journal()?;
+            Some(Journal::create(&self.input).map_err(RenameError::CreateJournal)?)
+        };
+
+        let matcher = ReferencesMatcher::new(&objects_vec)
+            .notion_hosts(&self.notion_hosts)
+            .external_links(self.external_links, placeholder_page.clone())
+            .read_pages_with(|path| fs::read_to_string(path));
+        let rewrite_errors = content_replacing::rename_refs_in_all_files(
+            &files_vec,
+            &matcher,
+            |path, new_contents| match journal.as_ref() {
+                Some(journal) => journal
+                    .stage_write(path, new_contents)
+                    .map_err(|source| RewriteError::Stage { path: path.clone(), source }),
+                None => Ok(()),
+            },
+            |path, renamed_contents| recorder.record(path, renamed_contents),
+        )?;
+        recorder.add_errors(rewrite_errors);
+
+        let created_dirs = path_replacing::get_created_dirs(&objects_vec, &existing_paths);
+        let renames = path_replacing::get_objects_files_renames(&objects_vec)
+            .into_iter()
+            .chain(path_replacing::get_directories_renames(&objects_vec));
+
+        if let Some(mut journal) = journal {
+            if self.needs_placeholder_page(&recorder) && !placeholder_page_exists {
+                journal
+                    .stage_write(&placeholder_page, get_placeholder_page_contents(&placeholder_page))
+                    .map_err(|source| RewriteError::Stage { path: placeholder_page.clone(), source })?;
+                recorder.record_placeholder_page(&placeholder_page, &files_vec);
+            }
+
+            // The directories pages are placed in exist before the pages are renamed
+            for created_dir in created_dirs.iter() {
+                journal.stage_create_dir(created_dir);
+                recorder.undo_record.add_created_dir(created_dir);
+            }
+            for (old_path, new_path) in renames {
+                journal.stage_rename(&old_path, &new_path);
+            }
+
+            journal.apply().map_err(RenameError::Apply)?;
+
+            // A run that did nothing keeps the record of the previous one as it is
+            if !recorder.undo_record.is_empty() {
+                self.chain_previous_undo_record(&mut recorder.undo_record)?;
+                recorder.undo_record.write(&self.input)
+                    .map_err(|source| CleanError::Io { path: self.input.join(UNDO_RECORD_FILE_NAME), source })?;
+            }
+        }
+
+        self.write_manifest(manifest, &objects_vec)?;
+
+        Ok(recorder.into_report(file_map, &objects_vec))
+    }
+
+    /// Reads the export from `source` and writes the cleaned export to `output_dir`.
+    fn clean_to(
+        &self,
+        source: &impl ExportSource,
+        file_map: &FileMapByName,
+        discovery_errors: Vec<DiscoveryError>,
+        output_dir: &Path,
+    ) -> Result<CleanReport, CleanError> {
+        let files_vec = file_map.values().flatten().collect::<Vec<&FileType>>();
+
+        let root = source.get_root().to_path_buf();
+        let mut existing_paths = source.get_all_paths();
+        let (placeholder_page, placeholder_page_exists) =
+            self.reserve_placeholder_page(&root, file_map, &mut existing_paths, |path| source.read_to_string(path));
+        let (objects_map, manifest, planning_errors) =
+            self.build_objects_map(file_map, &root, &existing_paths, |path| source.read_to_string(path))?;
+        let objects_vec = objects_map
+            .values()
+            .flatten()
+            .collect::<Vec<&NotionObject>>();
+
+        let mut recorder = RunRecorder::new(source.get_root(), &objects_vec, self.with_plan);
+        recorder.add_errors(discovery_errors);
+        recorder.add_errors(planning_errors);
+
+        let matcher = ReferencesMatcher::new(&objects_vec)
+            .notion_hosts(&self.notion_hosts)
+            .external_links(self.external_links, placeholder_page.clone())
+            .read_pages_with(|path| source.read_to_string(path));
+        let rewrite_errors = tree_writing::write_cleaned_tree(
+            source,
+            &files_vec,
+            &objects_vec,
+            &matcher,
+            output_dir,
+            |path, renamed_contents| recorder.record(path, renamed_contents),
+            self.dry_run,
+        );
+        recorder.add_errors(rewrite_errors);
+
+        // A placeholder page written by a previous run has been copied with the other files
+        if !self.dry_run && self.needs_placeholder_page(&recorder) && !placeholder_page_exists {
+            // The placeholder page is at the root, which is never renamed
+            let new_placeholder_page = output_dir.join(path_replacing::strip_root(&placeholder_page, source.get_root()));
+            File::create_new(&new_placeholder_page)
+                .and_then(|mut file| file.write_all(get_placeholder_page_contents(&placeholder_page).as_bytes()))
+                .map_err(|source| CleanError::Io { path: new_placeholder_page, source })?;
+            recorder.record_placeholder_page(&placeholder_page, &files_vec);
+        }
+
+        // They are created with the files placed in them
+        for created_dir in path_replacing::get_created_dirs(&objects_vec, &existing_paths) {
+            recorder.undo_record.add_created_dir(&created_dir);
+        }
+
+        if !self.dry_run {
+            self.chain_previous_undo_record(&mut recorder.undo_record)?;
+            // A run that did nothing only carries the record of the previous one over, if any
+            let undo_record = match &recorder.undo_record.previous {
+                Some(previous) if recorder.undo_record.is_empty() => Some(previous.as_ref()),
+                None if recorder.undo_record.is_empty() => None,
+                _ => Some(&recorder.undo_record),
+            };
+            if let Some(undo_record) = undo_record {
+                undo_record.write(output_dir)
+                    .map_err(|source| CleanError::Io { path: output_dir.join(UNDO_RECORD_FILE_NAME), source })?;
+            }
+        }
+
+        self.write_manifest(manifest, &objects_vec)?;
+
+        Ok(recorder.into_report(file_map, &objects_vec))
+    }
+
+    /// Builds the objects from the files, and finds their new names.
+    /// New names never take the path of one of the `existing_paths`, and their lengths are measured from `root`.
+    /// Pages are read with `read`, on all cores, only if the name template needs their creation times.
+    /// Also returns the names manifest, read from the manifest file if any, and the errors of the files left out.
+    fn build_objects_map(
+        &self,
+        file_map: &FileMapByName,
+        root: &Path,
+        existing_paths: &[PathBuf],
+        read: impl Fn(&Path) -> io::Result<String> + Sync,
+    ) -> Result<(ObjectsMapByName, NameManifest, Vec<PlanningError>), CleanError> {
+        let manifest = match &self.manifest_path {
+            Some(manifest_path) => NameManifest::read(manifest_path)
+                .map_err(|source| PlanningError::Manifest { path: manifest_path.clone(), source })?,
+            None => NameManifest::default(),
+        };
+
+        let (mut objects, errors) = NotionObject::create_objects_from_file_map(file_map);
+
+        // Pages and databases cleaned by a previous run are recognized thanks to the undo record it left
+        if self.input.is_dir() && UndoRecord::exists(&self.input) {
+            let undo_record = UndoRecord::read(&self.input)
+                .map_err(|source| CleanError::Io { path: self.input.join(UNDO_RECORD_FILE_NAME), source })?;
+            objects = NotionObject::mark_cleaned_objects(objects, undo_record.get_cleaned_objects());
+        }
+
+        let creation_times = if self.naming.name_template.uses_created() {
+            naming::find_creation_times(&objects, read)
+        } else {
+            HashMap::new()
+        };
+
+        let mut objects_map: ObjectsMapByName = NotionObject::build_map_by_name(objects);
+
+        path_replacing::resolve_new_names(&mut objects_map, &manifest, root, existing_paths, &creation_times, &self.naming)?;
+
+        Ok((objects_map, manifest, errors))
+    }
+
+    /// Records the names chosen in this run in the manifest, for the next runs.
+    fn write_manifest(&self, mut manifest: NameManifest, all_objects: &Vec<&NotionObject>) -> Result<(), CleanError> {
+        let Some(manifest_path) = &self.manifest_path else {
+            return Ok(());
+        };
+        if self.dry_run {
+            return Ok(());
+        }
+
+        manifest.update(all_objects);
+        manifest.write(manifest_path)
+            .map_err(|source| CleanError::Io { path: manifest_path.clone(), source })
+    }
+
+    /// Removes the journal of a run interrupted before modifying anything, if any, to start a new one.
+    fn discard_uncommitted_journal(&self) -> Result<(), CleanError> {
+        if !Journal::exists(&self.input) {
+            return Ok(());
+        }
+
+        let journal = self.open_journal()?;
+        if !journal.is_committed() {
+            journal.remove().map_err(|source| CleanError::Io { path: self.input.join(JOURNAL_DIR_NAME), source })?;
+        }
+        Ok(())
+    }
+
+    /// Finds the path of the placeholder page of `ExternalLinks::Placeholder`, see `get_placeholder_page_path`.
+    /// With `ExternalLinks::Placeholder`, the path is added to `existing_paths`, so that no object is renamed to it.
+    /// Also returns whether the page is already there, written by a previous run.
+    fn reserve_placeholder_page(
+        &self,
+        root: &Path,
+        file_map: &FileMapByName,
+        existing_paths: &mut Vec<PathBuf>,
+        read: impl FnMut(&Path) -> io::Result<String>,
+    ) -> (PathBuf, bool) {
+        let placeholder_page = get_placeholder_page_path(root, file_map, existing_paths, &self.naming, read);
+        let placeholder_page_exists = existing_paths.contains(&placeholder_page);
+        if self.external_links == ExternalLinks::Placeholder && !placeholder_page_exists {
+            existing_paths.push(placeholder_page.clone());
+        }
+        (placeholder_page, placeholder_page_exists)
+    }
+
+    /// Is the placeholder page of `ExternalLinks::Placeholder` linked to?
+    fn needs_placeholder_page(&self, recorder: &RunRecorder) -> bool {
+        self.external_links == ExternalLinks::Placeholder
+            && recorder
+                .warnings
+                .iter()
+                .any(|warning| matches!(warning.error, RenameRefsInFileError::ExternalNotionLink { .. }))
+    }
+
+    /// Names of the entries left out: the ones to ignore, and the ones created by this tool.
+    fn get_ignored_names(&self) -> HashSet<&str> {
+        let mut ignore: HashSet<&str> = HashSet::from_iter(self.ignore.iter().map(|s| s.as_str()));
+        ignore.insert(JOURNAL_DIR_NAME);
+        ignore.insert(UNDO_RECORD_FILE_NAME);
+        ignore
+    }
+
+    fn open_journal(&self) -> Result<Journal, CleanError> {
+        Ok(Journal::open(&self.input).map_err(RenameError::ReadJournal)?)
+    }
+}
+
+impl FileCounts {
+    pub fn from_file_map(file_map: &FileMapByName) -> Self {
+        let mut counts = Self::default();
+        for ft in file_map.values().flatten() {
+            match ft {
+                FileType::Markdown(_) => counts.markdown += 1,
+                FileType::Html(_) => counts.html += 1,
+                FileType::Csv(_) => counts.csv += 1,
+                FileType::CsvAll(_) => counts.csv_all += 1,
+                FileType::Dir(_) => counts.directories += 1,
+                FileType::OtherTxt(_) => counts.other_text += 1,
+                FileType::OtherBin(_) => counts.other_binary += 1,
+            }
+        }
+        counts
+    }
+
+    pub fn total(&self) -> usize {
+        self.markdown + self.html + self.csv + self.csv_all + self.directories + self.other_text + self.other_binary
+    }
+}
+
+/// Collects what happens to each file whose references are renamed.
+struct RunRecorder {
+    undo_record: UndoRecord,
+    plan: Option<RenamePlan>,
+    warnings: Vec<FileWarning>,
+    errors: Vec<CleanError>,
+}
+
+impl RunRecorder {
+    fn new(root: &Path, all_objects: &Vec<&NotionObject>, with_plan: bool) -> Self {
+        Self {
+            undo_record: UndoRecord::new(root, all_objects),
+            plan: with_plan.then(|| RenamePlan::new(root, all_objects)),
+            warnings: vec![],
+            errors: vec![],
+        }
+    }
+
+    fn add_errors(&mut self, errors: Vec<impl Into<CleanError>>) {
+        self.errors.extend(errors.into_iter().map(Into::into));
+    }
+
+    /// Records the replacements and the problems of the file at `path`. Its contents have already been written.
+    fn record(&mut self, path: &Path, renamed_contents: RenamedContents) {
+        if let Some(plan) = self.plan.as_mut() {
+            plan.add_file(path, &renamed_contents);
+        }
+
+        self.warnings.extend(
+            renamed_contents
+                .errors
+                .into_iter()
+                .map(|error| FileWarning { path: path.to_path_buf(), error }),
+        );
+        self.undo_record.add_file(path, renamed_contents.replacements);
+    }
+
+    /// Records the placeholder page written at `path`, if it's a new file.
+    fn record_placeholder_page(&mut self, path: &Path, all_files: &[&FileType]) {
+        if !all_files.iter().any(|ft| ft.get_path() == path) {
+            self.undo_record.add_created_file(path);
+        }
+    }
+
+    fn into_report(self, file_map: &FileMapByName, all_objects: &Vec<&NotionObject>) -> CleanReport {
+        CleanReport {
+            file_counts: FileCounts::from_file_map(file_map),
+            objects_count: all_objects.iter().filter(|obj| obj.is_page_or_dataset()).count(),
+            cleaned_objects_count: all_objects.iter().filter(|obj| matches!(obj, NotionObject::Cleaned(..))).count(),
+            warnings: self.warnings,
+            errors: self.errors,
+            plan: self.plan,
+            verify: None,
+        }
+    }
+}
+
+/// Returns the path of the placeholder page of `ExternalLinks::Placeholder`, at the root of the export.
+/// It's an HTML page for HTML exports, a Markdown page otherwise.
+/// It doesn't take the path of one of the `existing_paths`, unless it's the placeholder page of a previous run, read with `read`.
+/// Otherwise it gets a suffix, like the names of objects: `Not in this export 1`, `Not in this export 2`...
+fn get_placeholder_page_path(
+    root: &Path,
+    file_map: &FileMapByName,
+    existing_paths: &[PathBuf],
+    options: &NamingOptions,
+    mut read: impl FnMut(&Path) -> io::Result<String>,
+) -> PathBuf {
+    let counts = FileCounts::from_file_map(file_map);
+    let extension = if counts.html > 0 && counts.markdown == 0 { "html" } else { "md" };
+    let taken_paths = existing_paths
+        .iter()
+        .map(|path| options.get_collision_key(path))
+        .collect::<HashSet<PathBuf>>();
+
+    let mut name = PLACEHOLDER_PAGE_NAME.to_string();
+    let mut add = 0;
+    loop {
+        let path = root.join(format!("{}.{}", name, extension));
+        if !taken_paths.contains(&options.get_collision_key(&path)) {
+            return path;
+        }
+        let is_previous_placeholder_page = existing_paths.contains(&path)
+            && read(&path).is_ok_and(|contents| contents == get_placeholder_page_contents(&path));
+        if is_previous_placeholder_page {
+            return path;
+        }
+
+        add += 1;
+        name = format!("{} {}", PLACEHOLDER_PAGE_NAME, add);
+    }
+}
+
+/// Returns the contents of the placeholder page at `path`.
+fn get_placeholder_page_contents(path: &Path) -> String {
+    const TEXT: &str = "The links to this page pointed to Notion pages that are not part of this export.";
+    if path.extension().is_some_and(|ext| ext == "html") {
+        format!("<html><head><meta charset=\"utf-8\"/><title>{0}</title></head><body><h1>{0}</h1><p>{1}</p></body></html>\n", PLACEHOLDER_PAGE_NAME, TEXT)
+    } else {
+        format!("# {}\n\n{}\n", PLACEHOLDER_PAGE_NAME, TEXT)
+    }
+}
+
+/// Fails if writing to `output_dir` could overwrite existing files: it must not exist, or be an empty directory.
+fn check_output_dir_is_usable(output_dir: &Path) -> Result<(), CleanError> {
+    let metadata = match fs::metadata(output_dir) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(source) => return Err(CleanError::Io { path: output_dir.to_path_buf(), source }),
+    };
+    if !metadata.is_dir() {
+        return Err(CleanError::OutputDirNotADirectory(output_dir.to_path_buf()));
+    }
+
+    let mut entries = fs::read_dir(output_dir)
+        .map_err(|source| CleanError::Io { path: output_dir.to_path_buf(), source })?;
+    if entries.next().is_some() {
+        return Err(CleanError::OutputDirNotEmpty(output_dir.to_path_buf()));
+    }
+    Ok(())
+}
+
+/// Canonicalizes `path`, which may not exist yet: its deepest existing ancestor is canonicalized, and the rest appended to it.
+fn canonicalize_missing_path(path: &Path) -> io::Result<PathBuf> {
+    let path = path_replacing::normalize_path(&std::path::absolute(path)?);
+    for ancestor in path.ancestors() {
+        if let Ok(canonical_ancestor) = ancestor.canonicalize() {
+            return Ok(canonical_ancestor.join(path.strip_prefix(ancestor).unwrap())); // Should not panic, it's an ancestor
+        }
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+    use walkdir::WalkDir;
+
+    use super::*;
+
+    const HOME_UUID: &str = "11111111111111111111111111111111";
+    const CHILD_UUID: &str = "22222222222222222222222222222222";
+
+    /// Creates an export with a page and its child page, linking to each other.
+    fn create_export() -> TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let home_dir = dir.path().join(format!("Home {}", HOME_UUID));
+        fs::create_dir(&home_dir).unwrap();
+        fs::write(
+            dir.path().join(format!("Home {}.md", HOME_UUID)),
+            format!("# Home\n\n[Child](Home%20{0}/Child%20{1}.md)\n", HOME_UUID, CHILD_UUID),
+        )
+        .unwrap();
+        fs::write(home_dir.join(format!("Child {}.md", CHILD_UUID)), format!("# Child\n\n[Home](../Home%20{}.md)\n", HOME_UUID)).unwrap();
+        dir
+    }
+
+    /// Returns the paths of all entries in `root`, relative to it, with the bytes of the files.
+    fn read_tree(root: &Path) -> Vec<(String, Option<Vec<u8>>)> {
+        WalkDir::new(root)
+            .min_depth(1)
+            .sort_by_file_name()
+            .into_iter()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                let path = entry.path().strip_prefix(root).unwrap().to_string_lossy().replace('\\', "/");
+                let contents = entry.file_type().is_file().then(|| fs::read(entry.path()).unwrap());
+                (path, contents)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn writes_the_cleaned_export_to_the_output_dir_leaving_the_input_untouched() {
+        let input = create_export();
+        let output = tempfile::tempdir().unwrap();
+        let output_dir = output.path().join("cleaned");
+        let input_tree = read_tree(input.path());
+
+        let report = Cleaner::new(input.path()).output_dir(&output_dir).run().unwrap();
+        assert_eq!(report.objects_count, 2);
+        assert!(report.errors.is_empty());
+
+        assert_eq!(read_tree(input.path()), input_tree);
+        let file = |contents: &str| Some(contents.as_bytes().to_vec());
+        let mut output_tree = read_tree(&output_dir);
+        output_tree.retain(|(path, _)| path != UNDO_RECORD_FILE_NAME);
+        assert_eq!(
+            output_tree,
+            vec![
+                ("Home".to_string(), None),
+                ("Home/Child.md".to_string(), file("# Child\n\n[Home](../Home.md)\n")),
+                ("Home.md".to_string(), file("# Home\n\n[Child](Home/Child.md)\n")),
+            ]
+        );
+        assert!(UndoRecord::exists(&output_dir));
+    }
+
+    #[test]
+    fn leaves_no_undo_record_in_the_output_dir_when_nothing_is_renamed() {
+        let input = tempfile::tempdir().unwrap();
+        fs::write(input.path().join("Notes.md"), "# Notes\n").unwrap();
+        let output = tempfile::tempdir().unwrap();
+        let output_dir = output.path().join("cleaned");
+
+        let report = Cleaner::new(input.path()).output_dir(&output_dir).run().unwrap();
+        assert_eq!(report.objects_count, 0);
+        assert_eq!(read_tree(&output_dir), vec![("Notes.md".to_string(), Some(b"# Notes\n".to_vec()))]);
+    }
+
+    #[test]
+    fn rejects_an_output_dir_in_the_input() {
+        let input = create_export();
+        let input_tree = read_tree(input.path());
+
+        let error = Cleaner::new(input.path()).output_dir(input.path().join("cleaned")).run().unwrap_err();
+        assert!(matches!(error, CleanError::OutputDirInInput { .. }));
+        assert_eq!(read_tree(input.path()), input_tree);
+    }
+
+    #[test]
+    fn rejects_an_output_dir_that_is_not_empty() {
+        let input = create_export();
+        let output = tempfile::tempdir().unwrap();
+        fs::write(output.path().join("notes.md"), "notes").unwrap();
+
+        let error = Cleaner::new(input.path()).output_dir(output.path()).run().unwrap_err();
+        assert!(matches!(error, CleanError::OutputDirNotEmpty(_)));
+        assert_eq!(read_tree(output.path()), vec![("notes.md".to_string(), Some(b"notes".to_vec()))]);
+    }
+
+    #[test]
+    fn reports_the_files_it_can_not_rename_and_cleans_the_others() {
+        const TASKS_UUID: &str = "33333333333333333333333333333333";
+        const NOTES_UUID: &str = "44444444444444444444444444444444";
+        let input = create_export();
+        fs::write(input.path().join("notes"), "notes without extension").unwrap();
+        fs::write(input.path().join(format!("Tasks {}_all.csv", TASKS_UUID)), "Name\n").unwrap();
+        fs::create_dir(input.path().join(format!("Tasks {}", TASKS_UUID))).unwrap();
+        fs::write(input.path().join(format!("Notes {}.md", NOTES_UUID)), "# Notes\n").unwrap();
+        fs::write(input.path().join(format!("Notes {}.txt", NOTES_UUID)), "notes").unwrap();
+
+        let report = Cleaner::new(input.path()).run().unwrap();
+        assert_eq!(report.objects_count, 2);
+        assert_eq!(report.errors.len(), 2);
+        assert!(report.errors.iter().any(|error| matches!(error, CleanError::Planning(PlanningError::CsvAllWithoutCsv { key, .. }) if key.starts_with("Tasks"))));
+        assert!(report.errors.iter().any(|error| matches!(error, CleanError::Planning(PlanningError::InvalidPathCombination { key, .. }) if key.starts_with("Notes"))));
+
+        let mut paths: Vec<String> = read_tree(input.path()).into_iter().map(|(path, _)| path).collect();
+        paths.retain(|path| path != UNDO_RECORD_FILE_NAME);
+        assert_eq!(
+            paths,
+            vec![
+                "Home".to_string(),
+                "Home/Child.md".to_string(),
+                "Home.md".to_string(),
+                format!("Notes {}.md", NOTES_UUID),
+                format!("Notes {}.txt", NOTES_UUID),
+                format!("Tasks {}", TASKS_UUID),
+                format!("Tasks {}_all.csv", TASKS_UUID),
+                "notes".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn carries_the_undo_record_of_a_previous_run_over_to_the_output_dir() {
+        let input = create_export();
+        Cleaner::new(input.path()).run().unwrap();
+        let undo_record = fs::read(input.path().join(UNDO_RECORD_FILE_NAME)).unwrap();
+        let output = tempfile::tempdir().unwrap();
+        let output_dir = output.path().join("cleaned");
+
+        let report = Cleaner::new(input.path()).output_dir(&output_dir).run().unwrap();
+        assert_eq!(report.objects_count, 0);
+        assert_eq!(fs::read(output_dir.join(UNDO_RECORD_FILE_NAME)).unwrap(), undo_record);
+    }
+}
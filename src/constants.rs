@@ -1,7 +1,63 @@
 use std::sync::LazyLock;
 
 use indicatif::ProgressStyle;
+use regex::Regex;
 
-pub const NOTION_LINK_MARKER: &str = "notion.so";
+/// Hosts of the links to Notion pages, with their subdomains: `www.notion.so`, or `workspace.notion.site` for published pages.
+pub const DEFAULT_NOTION_HOSTS: [&str; 2] = ["notion.so", "notion.site"];
+
+/// Schemes of the absolute URLs that are not followed by `//`, like `mailto:name@example.com`.
+/// Other URLs with a scheme must have a `//` after it: `Meeting: Q1.md` is a relative link.
+pub const KNOWN_URL_SCHEMES: [&str; 7] = ["mailto", "tel", "sms", "data", "javascript", "about", "blob"];
+
+/// Matches the scheme of an absolute URL, with its `:`, and what follows it.
+pub static URL_SCHEME_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(?<scheme>[A-Za-z][A-Za-z0-9+.-]*):(?<rest>.*)").unwrap()); // Should not panic, the pattern is valid
+
+/// A Notion ID: 32 hexadecimal digits, or the dashed 8-4-4-4-12 form.
+pub const NOTION_UUID_PATTERN: &str = "[0-9a-fA-F]{32}|[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}";
+
+/// Matches the key of a Notion object: its name, a space, and its Notion ID.
+pub static NAME_UUID_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(&format!("^(?<name>.+) (?<uuid>{})$", NOTION_UUID_PATTERN)).unwrap()); // Should not panic, the pattern is valid
+
+/// Matches a Notion ID on its own, like in the slug of a Notion link: `Title-uuid`.
+pub static NOTION_UUID_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(&format!(r"\b(?:{})\b", NOTION_UUID_PATTERN)).unwrap()); // Should not panic, the pattern is valid
+
+/// Matches the name of a part of a big export, which Notion wraps in the zip file of the export: `Export-<uuid>-Part-1.zip`.
+pub static EXPORT_PART_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)^Export-.+-Part-\d+\.zip$").unwrap()); // Should not panic, the pattern is valid
+
+/// Matches a link to a page in a cell of a CSV file, the way Notion writes relations: `Title (Title%20uuid.md)`.
+pub static CSV_CELL_LINK_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\((?<link>[^()\s]+\.(?:md|html|csv))\)").unwrap()); // Should not panic, the pattern is valid
+
+/// Matches the value of the "Created time" property of a database row exported as HTML: `<time>@January 5, 2024 3:04 PM</time>`.
+pub static HTML_CREATED_TIME_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?s)class="property-row property-row-created_time">.*?<time>@?(?<date>[^<]+)</time>"#).unwrap() // Should not panic, the pattern is valid
+});
+
+/// Names of the "Created time" property of database rows, in the properties listed under the title of Markdown pages.
+pub const CREATED_TIME_PROPERTY_NAMES: [&str; 2] = ["Created", "Created time"];
+
+/// Formats of the dates written by Notion, depending on the settings of the property: `January 5, 2024 3:04 PM` by default.
+pub const NOTION_DATE_FORMATS: [&str; 6] = [
+    "%B %d, %Y %I:%M %p",
+    "%B %d, %Y %H:%M",
+    "%m/%d/%Y %I:%M %p",
+    "%m/%d/%Y %H:%M",
+    "%Y/%m/%d %I:%M %p",
+    "%Y/%m/%d %H:%M",
+];
+
+/// Name of the page the links to Notion pages that are not part of the export point to, with `ExternalLinks::Placeholder`.
+pub const PLACEHOLDER_PAGE_NAME: &str = "Not in this export";
+
+/// Directory created at the root of the input directory while it's modified in place. See `Journal`.
+pub const JOURNAL_DIR_NAME: &str = ".notion-export-cleaner-journal";
 
 pub static PROGRESS_BAR_STYLE: LazyLock<ProgressStyle> = LazyLock::new(|| ProgressStyle::default_bar().progress_chars("═█▓▒·"));
+
+/// File created at the root of the cleaned export, to be able to undo the cleaning. See `UndoRecord`.
+pub const UNDO_RECORD_FILE_NAME: &str = ".notion-export-cleaner-undo.json";
@@ -1,14 +1,25 @@
 use std::borrow::Cow;
-use std::cmp::max;
+use std::cmp::{max, Reverse};
+use std::collections::{HashMap, HashSet};
 use std::fs;
+use std::io;
 use std::ops::Range;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 
-use indicatif::ProgressIterator;
+use aho_corasick::AhoCorasick;
+use indicatif::ParallelProgressIterator;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 
-use crate::constants::{NOTION_LINK_MARKER, PROGRESS_BAR_STYLE};
+use crate::constants::{DEFAULT_NOTION_HOSTS, KNOWN_URL_SCHEMES, NOTION_UUID_REGEX, PROGRESS_BAR_STYLE, URL_SCHEME_REGEX};
+use crate::error::RewriteError;
 use crate::file_type::FileType;
+use crate::csv_cells;
+use crate::html_links::{self, HtmlLink};
+use crate::markdown_links::{self, LinkDestination};
 use crate::notion_object::NotionObject;
+use crate::{path_replacing, uriencoding};
 
 struct ReplaceDescriptor<'a> {
     byte_range: Range<usize>,
@@ -16,17 +27,37 @@ struct ReplaceDescriptor<'a> {
 }
 
 impl<'a> ReplaceDescriptor<'a> {
-    fn new_from_borrowed(start: usize, end:usize, new_text: &'a str) -> Self {
-        assert!(start < end);
-        Self { byte_range: (start..end), new_text: Cow::Borrowed(new_text) }
+    /// Returns None if the range is empty: there is nothing to replace.
+    fn new_from_borrowed(start: usize, end:usize, new_text: &'a str) -> Option<Self> {
+        (start < end).then_some(Self { byte_range: (start..end), new_text: Cow::Borrowed(new_text) })
     }
 
-    fn new_from_owned(start: usize, end:usize, new_text: String) -> Self {
-        assert!(start < end);
-        Self { byte_range: (start..end), new_text: Cow::Owned(new_text) }
+    /// Returns None if the range is empty: there is nothing to replace.
+    fn new_from_owned(start: usize, end:usize, new_text: String) -> Option<Self> {
+        (start < end).then_some(Self { byte_range: (start..end), new_text: Cow::Owned(new_text) })
     }
 }
 
+/// A replacement that has been done in the contents of a file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Replacement {
+    /// Where `old_text` was in the old contents
+    pub byte_range: Range<usize>,
+    pub old_text: String,
+    pub new_text: String,
+}
+
+/// The new contents of a file, and the replacements that led to them.
+pub struct RenamedContents {
+    pub contents: String,
+    /// Sorted by position, and not overlapping.
+    pub replacements: Vec<Replacement>,
+    /// Replacements that were not done because they overlapped with another one.
+    pub dropped_replacements: Vec<Replacement>,
+    /// Problems found in the new contents.
+    pub errors: Vec<RenameRefsInFileError>,
+}
+
 #[derive(Debug, Clone, thiserror::Error)]
 pub enum RenameRefsInFileError {
     #[error("The uuid {uuid} ({new_name}) remains in renamed content:\n\tFound in: '...{window_where_uuid_appears}...'\n\tStrings replaced: {looked_for:?}")]
@@ -36,133 +67,627 @@ pub enum RenameRefsInFileError {
         window_where_uuid_appears: String,
         looked_for: Vec<String>,
     },
+    #[error("Link to the Notion page {uuid}, which is not part of the export: {link}")]
+    ExternalNotionLink {
+        uuid: String,
+        link: String,
+    },
 }
 
-fn prepare_rename_refs_in_content<'a>(file_contents: &str, file_path: &PathBuf, all_objects: &Vec<&'a NotionObject>) -> Vec<ReplaceDescriptor<'a>> {
-    const REPLACE_DESCRIPTORS_ESTIMATED_AMOUNT: usize = 2048;
-    let mut replace_descriptors: Vec<ReplaceDescriptor> = Vec::with_capacity(REPLACE_DESCRIPTORS_ESTIMATED_AMOUNT);
+/// What to do with the links to Notion pages that are not part of the export.
+/// They are reported as warnings in any case.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExternalLinks {
+    /// Leave them as they are: they still point to Notion.
+    #[default]
+    Keep,
+    /// Replace them with their text, or with the title of the page when they have no text.
+    PlainText,
+    /// Point them to a placeholder page, at the root of the export.
+    Placeholder,
+}
+
+/// Reads a page, from any thread, see `ReferencesMatcher::read_pages_with`.
+type ReadPage<'a> = Box<dyn Fn(&Path) -> io::Result<String> + Sync + 'a>;
+
+/// Finds all references to all objects in one pass over the contents of a file, whatever the number of objects.
+/// It's built once from all objects, and used for every file.
+pub struct ReferencesMatcher<'a> {
+    /// Finds the old references of all objects, with all their encodings
+    references_automaton: AhoCorasick,
+    /// The new reference of each pattern of `references_automaton`
+    new_references: Vec<&'a str>,
+    /// Finds the UUIDs of all objects
+    uuids_automaton: AhoCorasick,
+    /// The object of each pattern of `uuids_automaton`
+    objects_by_uuid: Vec<&'a NotionObject>,
+    /// To find the final paths of the objects and of the files, with normalized paths, see `get_final_path`
+    renamed_paths: HashMap<PathBuf, PathBuf>,
+    /// Are some files placed in other directories, see `NameTemplate`? Then the relative links of Markdown files are rewritten
+    /// from the paths they point to, see `rewrite_relative_link`: replacing the names in them is not enough.
+    moves_files: bool,
+    /// Hosts of the Notion links, lowercase, see `is_notion_link`
+    notion_hosts: Vec<String>,
+    external_links: ExternalLinks,
+    /// Where the links to Notion pages that are not part of the export point, with `ExternalLinks::Placeholder`
+    placeholder_page: PathBuf,
+    /// The anchors of the headings of the Markdown pages, by path of the page, see `get_heading_anchors`
+    heading_anchors: HashMap<PathBuf, OnceLock<HashSet<String>>>,
+    /// Reads the Markdown pages to find the anchors of their headings, see `read_pages_with`
+    read_page: Option<ReadPage<'a>>,
+}
 
-    let notion_links_markers: Vec<Range<usize>> = file_contents.match_indices(NOTION_LINK_MARKER)
-        .filter_map(|(notion_link_start, excerpt)| -> Option<Range<usize>> {
-            const HTTPS_WINDOW_BYTE_SIZE: usize = 30; // "https://www." is 12 bytes long, make room for a bit more
-            const UUID_BYTE_SIZE: usize = 36;
-            const AFTER_LINK_WINDOW_BYTE_SIZE: usize = 60; // /?arg=smthg is 11 bytes long, make room for a bit more
+impl<'a> ReferencesMatcher<'a> {
+    pub fn new(all_objects: &Vec<&'a NotionObject>) -> Self {
+        let mut old_references = vec![];
+        let mut new_references = vec![];
+        let mut old_references_seen = HashSet::new();
+        let mut uuids = vec![];
+        let mut objects_by_uuid = vec![];
 
-            let window_start = if notion_link_start <= HTTPS_WINDOW_BYTE_SIZE { 0 } else { file_contents.floor_char_boundary(notion_link_start - HTTPS_WINDOW_BYTE_SIZE) };
-            let window_end = file_contents.ceil_char_boundary(notion_link_start + excerpt.len() + UUID_BYTE_SIZE + AFTER_LINK_WINDOW_BYTE_SIZE);
+        for object in all_objects {
+            let (obj_info, replacable_refs) = match object {
+                NotionObject::Page(obj_info, replacable_refs)
+                | NotionObject::Database(obj_info, _, replacable_refs)
+                | NotionObject::Cleaned(obj_info, replacable_refs) => (obj_info, replacable_refs),
+                _ => continue,
+            };
 
-            if let Some(last_open_par_index) = file_contents[window_start..notion_link_start].find('(').and_then(|found| Some(window_start + found)) {
-                if let Some(first_close_par_index) = file_contents[notion_link_start..window_end].find(')').and_then(|found| Some(notion_link_start + found)) {
-                    let range_without_parenthesis = (last_open_par_index + 1)..first_close_par_index;
-                    assert!(file_contents[range_without_parenthesis.clone()].contains(NOTION_LINK_MARKER));
-                    return Some(range_without_parenthesis);
+            uuids.push(obj_info.uuid.as_str());
+            objects_by_uuid.push(*object);
+
+            // The same reference can have the same text in several encodings: one pattern is enough
+            for replacable_ref in replacable_refs.iter().flat_map(|refs| refs.global_references.iter()) {
+                if !replacable_ref.old_ref.is_empty() && old_references_seen.insert(replacable_ref.old_ref.as_str()) {
+                    old_references.push(replacable_ref.old_ref.as_str());
+                    new_references.push(replacable_ref.new_ref.as_str());
                 }
             }
+        }
 
-            None
-        })
-        .collect();
+        let renamed_paths = path_replacing::build_renamed_paths(all_objects);
+        let moves_files = renamed_paths.iter().any(|(old_path, new_path)| old_path.parent() != new_path.parent());
 
-    for object in all_objects {
+        Self {
+            references_automaton: AhoCorasick::new(old_references).unwrap(), // Should not panic, there is no size limit by default
+            new_references,
+            uuids_automaton: AhoCorasick::new(uuids).unwrap(), // Should not panic, there is no size limit by default
+            objects_by_uuid,
+            // Links are resolved to normalized paths, see `get_relative_link_to_path`
+            renamed_paths: renamed_paths
+                .into_iter()
+                .map(|(old_path, new_path)| (path_replacing::normalize_path(&old_path), path_replacing::normalize_path(&new_path)))
+                .collect(),
+            moves_files,
+            notion_hosts: DEFAULT_NOTION_HOSTS.iter().map(|host| host.to_string()).collect(),
+            external_links: ExternalLinks::default(),
+            placeholder_page: PathBuf::new(),
+            // Pages are only read if a link points to one of their headings
+            heading_anchors: all_objects
+                .iter()
+                .filter(|object| matches!(object, NotionObject::Page(..) | NotionObject::Cleaned(..)))
+                .map(|object| object.get_path())
+                .filter(|path| path.extension().is_some_and(|ext| ext == "md"))
+                .map(|path| (path.to_path_buf(), OnceLock::new()))
+                .collect(),
+            read_page: None,
+        }
+    }
 
-        let (obj_info, replacable_refs) = match object {
-            NotionObject::Page(obj_info, replacable_refs) | NotionObject::Database(obj_info, _, replacable_refs) => {
-                (obj_info, replacable_refs)
-            }
-            _ => continue,
+    /// Sets the hosts of the links to rewrite as links to Notion pages, like `notion.so`, replacing the default ones.
+    /// Each host matches its subdomains too: `notion.site` matches `workspace.notion.site`.
+    pub fn notion_hosts<S: AsRef<str>>(mut self, hosts: impl IntoIterator<Item = S>) -> Self {
+        self.notion_hosts = hosts
+            .into_iter()
+            .map(|host| normalize_notion_host(host.as_ref()))
+            .filter(|host| !host.is_empty())
+            .collect();
+        self
+    }
+
+    /// Sets what to do with the links to Notion pages that are not part of the export.
+    /// With `ExternalLinks::Placeholder`, they point to `placeholder_page`.
+    pub fn external_links(mut self, external_links: ExternalLinks, placeholder_page: PathBuf) -> Self {
+        self.external_links = external_links;
+        self.placeholder_page = placeholder_page;
+        self
+    }
+
+    /// Sets how the Markdown pages are read to find the anchors of their headings, see `get_link_fragment`.
+    /// A page is read once, from any thread, the first time a link points to one of its blocks.
+    /// Without it, the fragments of the links to Markdown pages are kept as they are.
+    pub fn read_pages_with(mut self, read_page: impl Fn(&Path) -> io::Result<String> + Sync + 'a) -> Self {
+        self.read_page = Some(Box::new(read_page));
+        self
+    }
+
+    /// Returns the anchors of the headings of the Markdown page at `path`, read the first time they are needed.
+    /// A page that can't be read has no anchors: its errors are reported when its references are renamed.
+    fn get_heading_anchors(&self, path: &Path) -> Option<&HashSet<String>> {
+        let read_page = self.read_page.as_ref()?;
+        let anchors = self.heading_anchors.get(path)?.get_or_init(|| match read_page(path) {
+            Ok(contents) => markdown_links::find_heading_anchors(&contents).into_iter().collect(),
+            Err(_) => HashSet::new(),
+        });
+        Some(anchors)
+    }
+
+    /// Returns the link to `object` from the file at `file_path`, once both are renamed:
+    /// the relative path from the directory of the file, percent-encoded.
+    fn get_relative_link(&self, object: &NotionObject, file_path: &Path) -> Option<String> {
+        self.get_relative_link_to_path(object.get_path(), file_path)
+    }
+
+    /// Same as `get_relative_link`, for the file or directory at `path`.
+    fn get_relative_link_to_path(&self, path: &Path, file_path: &Path) -> Option<String> {
+        let final_path = self.get_final_path(path);
+        let file_final_path = self.get_final_path(file_path);
+        let relative_path = pathdiff::diff_paths(final_path, file_final_path.parent()?)?;
+
+        let components = relative_path
+            .components()
+            .map(|component| Some(uriencoding::encode(component.as_os_str().to_str()?).into_owned()))
+            .collect::<Option<Vec<String>>>()?;
+        Some(components.join("/"))
+    }
+
+    /// Returns the path `path` will have once renamed, normalized: the input can be like `./export`, or `.`.
+    fn get_final_path(&self, path: &Path) -> PathBuf {
+        path_replacing::get_final_path(&path_replacing::normalize_path(path), &self.renamed_paths)
+    }
+
+    /// Returns the fragment of the link to `object`, with its `#`, from the fragment of the Notion `link` (if any).
+    /// The fragment of a Notion link is the ID of a block. In HTML pages, it's the ID of the element of the block, with dashes.
+    /// Markdown pages have no IDs: if `link_text` is the text of a heading of the page, the fragment is the anchor of this heading.
+    /// Other fragments are kept as they are.
+    fn get_link_fragment(&self, object: &NotionObject, link: &str, link_text: Option<&str>) -> String {
+        let Some((_, fragment)) = link.split_once('#') else {
+            return String::new();
         };
+        if fragment.is_empty() {
+            return String::new();
+        }
 
-        // Most refs are simple find & replace
-        if let Some(replacable_refs) = replacable_refs {
-            for replacable_ref in &replacable_refs.global_references {
-                file_contents.match_indices(&replacable_ref.old_ref).for_each(|(start, m)| {
-                    replace_descriptors.push(ReplaceDescriptor::new_from_borrowed(start, start + m.len(), &replacable_ref.new_ref));
-                });
+        let block_id = fragment.replace('-', "").to_ascii_lowercase();
+        if block_id.len() != 32 || !block_id.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return format!("#{}", fragment);
+        }
+
+        let path = object.get_path();
+        if path.extension().is_some_and(|ext| ext == "html") {
+            return format!("#{}-{}-{}-{}-{}", &block_id[..8], &block_id[8..12], &block_id[12..16], &block_id[16..20], &block_id[20..]);
+        }
+
+        let heading_anchor = link_text.map(markdown_links::get_heading_anchor).filter(|anchor| !anchor.is_empty());
+        match heading_anchor {
+            Some(anchor) if self.get_heading_anchors(path).is_some_and(|anchors| anchors.contains(&anchor)) => format!("#{}", anchor),
+            _ => format!("#{}", fragment),
+        }
+    }
+
+    /// Rewrites the URL of `link`, in an HTML file at `file_path`.
+    /// Links to Notion become relative links, relative links to renamed files follow them.
+    /// Links to Notion pages that are not part of the export are reported in `errors`, and rewritten following `external_links`.
+    /// Returns None if the link doesn't change.
+    fn rewrite_html_link(&self, file_contents: &str, link: &HtmlLink, file_path: &Path, errors: &mut Vec<RenameRefsInFileError>) -> Option<ReplaceDescriptor<'a>> {
+        let url = html_escape::decode_html_entities(&file_contents[link.range.clone()]);
+
+        let new_url = if self.is_notion_link(&url) {
+            match self.find_object_in_link(&url) {
+                Some(object) => self.get_relative_link(object, file_path)? + &self.get_link_fragment(object, &url, None),
+                None => {
+                    let uuid = self.find_uuid_in_link(&url)?;
+                    errors.push(RenameRefsInFileError::ExternalNotionLink { uuid: uuid.to_string(), link: url.to_string() });
+                    match self.external_links {
+                        ExternalLinks::Keep => return None,
+                        // Without its link attribute, the element is plain text
+                        ExternalLinks::PlainText => {
+                            return ReplaceDescriptor::new_from_owned(link.attribute_range.start, link.attribute_range.end, String::new());
+                        }
+                        ExternalLinks::Placeholder => self.get_relative_link_to_path(&self.placeholder_page, file_path)?,
+                    }
+                }
             }
+        } else {
+            self.rewrite_relative_link(&url, file_path)?
+        };
+
+        let new_url = html_escape::encode_quoted_attribute(&new_url).into_owned();
+        ReplaceDescriptor::new_from_owned(link.range.start, link.range.end, new_url)
+    }
+
+    /// Returns the relative link `url` of the file at `file_path` once the file and the path it points to are renamed.
+    /// Absolute URLs, and links to the same page, are left as they are.
+    /// Returns None if the link doesn't change.
+    fn rewrite_relative_link(&self, url: &str, file_path: &Path) -> Option<String> {
+        if url.starts_with(['/', '#']) || is_absolute_url(url) {
+            return None;
+        }
+
+        let (path, suffix) = url.split_at(url.find(['?', '#']).unwrap_or(url.len()));
+        let parent = path_replacing::normalize_path(file_path.parent()?);
+        let linked_path = path_replacing::normalize_path(&parent.join(uriencoding::decode(path)?.as_ref()));
+        // The file can move as well as the path it points to
+        let file_final_path = self.get_final_path(file_path);
+        if pathdiff::diff_paths(self.get_final_path(&linked_path), file_final_path.parent()?) == pathdiff::diff_paths(&linked_path, &parent) {
+            return None;
         }
+        Some(self.get_relative_link_to_path(&linked_path, file_path)? + suffix)
+    }
 
-        // Some refs are Notion paths
-        // https://www.notion.so/uuid?arg=smthg
-        // We will replace them with relative disk paths
-        if let Some(relative_path_with_new_name) = object.get_relative_path(file_path) {
-            for range in &notion_links_markers {
-                let window = &file_contents[range.clone()];
-                if window.contains(&obj_info.uuid) {
-                    replace_descriptors.push(ReplaceDescriptor::new_from_owned(range.start, range.end, relative_path_with_new_name.clone()));
+    /// Rewrites the link at `destination`, in a text file at `file_path`, if it points to a Notion page that is not part of the export.
+    /// The link is reported in `errors`, and rewritten following `external_links`.
+    fn rewrite_external_link(
+        &self,
+        file_contents: &str,
+        destination: &LinkDestination,
+        file_path: &Path,
+        errors: &mut Vec<RenameRefsInFileError>,
+    ) -> Option<ReplaceDescriptor<'a>> {
+        let link = destination.get_link(file_contents);
+        let uuid = self.find_uuid_in_link(link)?;
+        errors.push(RenameRefsInFileError::ExternalNotionLink { uuid: uuid.to_string(), link: link.to_string() });
+
+        let is_markdown = file_path.extension().is_some_and(|ext| ext == "md");
+        let title = get_title_from_notion_link(link);
+        let range = &destination.range;
+        match self.external_links {
+            ExternalLinks::Keep => None,
+            ExternalLinks::PlainText => match &destination.link_and_text {
+                Some((link_range, text_range)) => {
+                    let text = if text_range.is_empty() {
+                        markdown_links::escape_link_text(&title)
+                    } else {
+                        file_contents[text_range.clone()].to_string()
+                    };
+                    ReplaceDescriptor::new_from_owned(link_range.start, link_range.end, text)
                 }
+                None if destination.is_bare => ReplaceDescriptor::new_from_owned(range.start, range.end, markdown_links::escape_link_text(&title)),
+                None if !is_markdown => ReplaceDescriptor::new_from_owned(range.start, range.end, title),
+                // A reference definition has no text of its own, its links are left as they are
+                None => None,
+            },
+            ExternalLinks::Placeholder => {
+                let placeholder_link = self.get_relative_link_to_path(&self.placeholder_page, file_path)?;
+                let new_text = if destination.is_bare {
+                    format!("[{}]({})", markdown_links::escape_link_text(&title), placeholder_link)
+                } else {
+                    placeholder_link
+                };
+                ReplaceDescriptor::new_from_owned(range.start, range.end, new_text)
+            }
+        }
+    }
+
+    /// Returns the UUID of the Notion page a Notion link points to: the last one in the path of the link,
+    /// since a page can be linked with the UUIDs of its parents, like `Parent-<uuid>/Child-<uuid>`.
+    /// The UUID in the fragment is the ID of a block, which can be the one of a page: it's not looked for.
+    fn find_uuid_in_link<'l>(&self, link: &'l str) -> Option<&'l str> {
+        let path = link.split(['?', '#']).next()?;
+        NOTION_UUID_REGEX.find_iter(path).last().map(|m| m.as_str())
+    }
+
+    /// Finds the object the Notion `link` points to, if it's part of the export. See `find_uuid_in_link`.
+    /// Notion links can have dashed or uppercase UUIDs, like `11111111-1111-...`, while the UUIDs of the objects are normalized,
+    /// see `notion_object::split_name_and_uuid`.
+    fn find_object_in_link(&self, link: &str) -> Option<&'a NotionObject> {
+        let uuid = self.find_uuid_in_link(link)?.replace('-', "").to_ascii_lowercase();
+        let object = self.find_objects_by_uuid(&uuid).map(|(_, object)| object).next();
+        object
+    }
+
+    /// Is `url` a link to Notion? See `is_notion_link`.
+    fn is_notion_link(&self, url: &str) -> bool {
+        is_notion_link(url, &self.notion_hosts)
+    }
+
+    /// Finds the objects whose UUID appears in `text`, with the position of the UUID.
+    /// An object is returned for each of its UUID occurrences.
+    fn find_objects_by_uuid<'t>(&'t self, text: &'t str) -> impl Iterator<Item = (usize, &'a NotionObject)> + 't {
+        self.uuids_automaton
+            .find_overlapping_iter(text)
+            .map(|m| (m.start(), self.objects_by_uuid[m.pattern().as_usize()]))
+    }
+}
+
+/// Returns `host` the way `is_notion_link` compares it: lowercase, without the `*.` of a wildcard.
+pub fn normalize_notion_host(host: &str) -> String {
+    host.trim_start_matches("*.").to_ascii_lowercase()
+}
+
+/// Is `url` a link to Notion: is its host one of the `notion_hosts` (normalized), or one of their subdomains?
+/// The scheme is optional, like in `www.notion.so/Title-uuid`.
+pub fn is_notion_link(url: &str, notion_hosts: &[String]) -> bool {
+    let without_scheme = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let host = without_scheme.split(['/', '?', '#', ':']).next().unwrap_or_default().to_ascii_lowercase();
+    notion_hosts
+        .iter()
+        .any(|notion_host| host.strip_suffix(notion_host.as_str()).is_some_and(|subdomain| subdomain.is_empty() || subdomain.ends_with('.')))
+}
+
+/// Is `url` an absolute URL, rather than a relative link?
+/// Its scheme must be one of the `KNOWN_URL_SCHEMES`, or be followed by `//`:
+/// Notion doesn't encode the `:` of page names, so `Meeting:%20Q1.md` is a relative link.
+pub fn is_absolute_url(url: &str) -> bool {
+    URL_SCHEME_REGEX.captures(url).is_some_and(|captures| {
+        captures["rest"].starts_with("//") || KNOWN_URL_SCHEMES.iter().any(|scheme| scheme.eq_ignore_ascii_case(&captures["scheme"]))
+    })
+}
+
+/// Returns the title of the Notion page a Notion link points to, from the last part of its path, like `Title-uuid`.
+fn get_title_from_notion_link(link: &str) -> String {
+    const UNKNOWN_TITLE: &str = "Notion page";
+
+    let path = link.split(['?', '#']).next().unwrap_or_default();
+    let slug = path.trim_end_matches('/').rsplit('/').next().unwrap_or_default();
+    let title = NOTION_UUID_REGEX.replace_all(slug, "");
+    match uriencoding::decode(title.trim_matches('-')) {
+        Some(title) if !title.is_empty() => title.replace('-', " "),
+        _ => UNKNOWN_TITLE.to_string(),
+    }
+}
+
+/// Finds the Notion links in the contents of a file, see `ReferencesMatcher::is_notion_link`.
+/// Markdown links are parsed, see `markdown_links`.
+/// In other files, like the cells of CSV files, URLs are found in the text like bare URLs in Markdown.
+fn find_notion_links(file_contents: &str, file_path: &Path, matcher: &ReferencesMatcher) -> Vec<LinkDestination> {
+    let destinations = if file_path.extension().is_some_and(|ext| ext == "md") {
+        markdown_links::find_link_destinations(file_contents)
+    } else {
+        markdown_links::find_bare_urls(file_contents, 0..file_contents.len())
+            .into_iter()
+            .map(|range| LinkDestination::new(range, false))
+            .collect()
+    };
+
+    destinations
+        .into_iter()
+        .filter(|destination| matcher.is_notion_link(destination.get_link(file_contents)))
+        .collect()
+}
+
+/// Returns the replacements to do in the contents of the file at `file_path`,
+/// the replacements already dropped because they overlapped, in the cells of CSV files,
+/// and the problems found, like links to Notion pages that are not part of the export.
+fn prepare_rename_refs_in_content<'a>(
+    file_contents: &str,
+    file_path: &Path,
+    matcher: &ReferencesMatcher<'a>,
+) -> (Vec<ReplaceDescriptor<'a>>, Vec<Replacement>, Vec<RenameRefsInFileError>) {
+    const REPLACE_DESCRIPTORS_ESTIMATED_AMOUNT: usize = 2048;
+    let mut replace_descriptors: Vec<ReplaceDescriptor> = Vec::with_capacity(REPLACE_DESCRIPTORS_ESTIMATED_AMOUNT);
+    let mut dropped_replacements = vec![];
+    let mut errors = vec![];
+
+    match file_path.extension().and_then(|ext| ext.to_str()) {
+        // In HTML files, only the links are rewritten, see `html_links`
+        Some("html") => {
+            for link in html_links::find_link_attribute_values(file_contents) {
+                replace_descriptors.extend(matcher.rewrite_html_link(file_contents, &link, file_path, &mut errors));
+            }
+        }
+        Some("csv") => prepare_rename_refs_in_csv(&mut replace_descriptors, &mut dropped_replacements, &mut errors, file_contents, file_path, matcher),
+        _ => prepare_rename_refs_in_text(&mut replace_descriptors, &mut errors, file_contents, file_path, matcher),
+    }
+
+    (replace_descriptors, dropped_replacements, errors)
+}
+
+/// Renames the references in each cell of a CSV file, like in a text file.
+/// The cells that change are written again, quoted if needed, so that the file stays valid CSV.
+fn prepare_rename_refs_in_csv(
+    replace_descriptors: &mut Vec<ReplaceDescriptor>,
+    dropped_replacements: &mut Vec<Replacement>,
+    errors: &mut Vec<RenameRefsInFileError>,
+    file_contents: &str,
+    file_path: &Path,
+    matcher: &ReferencesMatcher,
+) {
+    for cell in csv_cells::read_cells(file_contents) {
+        let mut cell_descriptors = vec![];
+        prepare_rename_refs_in_text(&mut cell_descriptors, errors, &cell.value, file_path, matcher);
+
+        let Some(renamed_cell) = do_rename_refs_in_content(&cell.value, cell_descriptors) else {
+            continue;
+        };
+        // The overlaps are found in the value of the cell, but are reported where they are in the file
+        dropped_replacements.extend(renamed_cell.dropped_replacements.into_iter().map(|dropped| Replacement {
+            byte_range: cell.get_position_in_file(dropped.byte_range.start)..cell.get_position_in_file(dropped.byte_range.end),
+            ..dropped
+        }));
+        let new_cell = csv_cells::write_cell(&renamed_cell.contents, cell.is_quoted);
+        if new_cell != file_contents[cell.range.clone()] {
+            replace_descriptors.extend(ReplaceDescriptor::new_from_owned(cell.range.start, cell.range.end, new_cell));
+        }
+    }
+}
+
+/// Renames the references in a text file: the old names of the objects, and the links to Notion.
+fn prepare_rename_refs_in_text<'a>(
+    replace_descriptors: &mut Vec<ReplaceDescriptor<'a>>,
+    errors: &mut Vec<RenameRefsInFileError>,
+    file_contents: &str,
+    file_path: &Path,
+    matcher: &ReferencesMatcher<'a>,
+) {
+    let notion_links = find_notion_links(file_contents, file_path, matcher);
+
+    // When files are placed in other directories, the relative links of a Markdown file can change even if their names don't:
+    // they are rewritten from the paths they point to
+    let mut relative_links = vec![];
+    if matcher.moves_files && file_path.extension().is_some_and(|ext| ext == "md") {
+        for destination in markdown_links::find_link_destinations(file_contents).into_iter().filter(|destination| !destination.is_bare) {
+            let link = destination.get_link(file_contents);
+            if matcher.is_notion_link(link) || link.starts_with(['/', '#']) || is_absolute_url(link) {
+                continue;
             }
+            replace_descriptors.extend(
+                matcher
+                    .rewrite_relative_link(link, file_path)
+                    .and_then(|new_link| ReplaceDescriptor::new_from_owned(destination.range.start, destination.range.end, new_link)),
+            );
+            relative_links.push(destination.range);
         }
     }
 
-    replace_descriptors
+    // Most refs are simple find & replace
+    for m in matcher.references_automaton.find_overlapping_iter(file_contents) {
+        if relative_links.iter().any(|range| m.start() < range.end && range.start < m.end()) {
+            continue;
+        }
+        replace_descriptors.extend(ReplaceDescriptor::new_from_borrowed(m.start(), m.end(), matcher.new_references[m.pattern().as_usize()]));
+    }
+
+    // Some refs are Notion paths
+    // https://www.notion.so/uuid?arg=smthg
+    // We will replace them with relative disk paths
+    // A relative path is not a link on its own: bare URLs become links with the name of the page as text
+    // The fragment, the ID of a block, is kept: see `get_link_fragment`
+    for destination in notion_links {
+        let LinkDestination { range, is_bare, link_and_text } = &destination;
+        let link = destination.get_link(file_contents);
+        let link_text = link_and_text.as_ref().map(|(_, text_range)| &file_contents[text_range.clone()]);
+        let Some(object) = matcher.find_object_in_link(link) else {
+            replace_descriptors.extend(matcher.rewrite_external_link(file_contents, &destination, file_path, errors));
+            continue;
+        };
+
+        if let Some(relative_link) = matcher.get_relative_link(object, file_path) {
+            let relative_link = relative_link + &matcher.get_link_fragment(object, link, link_text);
+            let new_text = if *is_bare {
+                format!("[{}]({})", markdown_links::escape_link_text(object.get_name()), relative_link)
+            } else {
+                relative_link
+            };
+            replace_descriptors.extend(ReplaceDescriptor::new_from_owned(range.start, range.end, new_text));
+        }
+    }
 }
 
 /// Renames all references to all objects in given file.
-fn do_rename_refs_in_content(file_contents: &str, mut descriptors: Vec<ReplaceDescriptor>) -> Option<String>
+fn do_rename_refs_in_content(file_contents: &str, mut descriptors: Vec<ReplaceDescriptor>) -> Option<RenamedContents>
 {
     if descriptors.is_empty() {
         return None;
     }
 
-    descriptors.sort_by_key(|d| d.byte_range.start);
+    // At the same position, the longest reference wins
+    descriptors.sort_by_key(|d| (d.byte_range.start, Reverse(d.byte_range.end)));
 
-    for desc_index in (1..descriptors.len()).rev() {
-        let descriptor = &descriptors[desc_index];
-        let previous_descriptor = &descriptors[desc_index - 1];
+    // A reference is dropped if it overlaps any kept one, not only the previous one:
+    // a long reference can contain several shorter ones
+    let mut dropped_replacements = vec![];
+    let mut kept_descriptors: Vec<ReplaceDescriptor> = Vec::with_capacity(descriptors.len());
+    for descriptor in descriptors {
+        let Some(last_kept) = kept_descriptors.last() else {
+            kept_descriptors.push(descriptor);
+            continue;
+        };
+        if descriptor.byte_range.start >= last_kept.byte_range.end {
+            kept_descriptors.push(descriptor);
+            continue;
+        }
 
-        if descriptor.byte_range.start < previous_descriptor.byte_range.end {
-            descriptors.remove(desc_index);
+        // The same reference can be found with several encodings: this is not worth reporting
+        let is_duplicate = descriptor.byte_range == last_kept.byte_range && descriptor.new_text == last_kept.new_text;
+        if is_duplicate {
+            continue;
         }
+
+        let ReplaceDescriptor { byte_range, new_text } = descriptor;
+        dropped_replacements.push(Replacement {
+            old_text: file_contents[byte_range.clone()].to_owned(),
+            byte_range,
+            new_text: new_text.into_owned(),
+        });
     }
 
     let mut new_contents = String::with_capacity(file_contents.len());
+    let mut replacements = Vec::with_capacity(kept_descriptors.len());
 
     let mut last_end = 0;
-    for ReplaceDescriptor { byte_range, new_text } in &descriptors {
+    for ReplaceDescriptor { byte_range, new_text } in kept_descriptors {
         new_contents.push_str(&file_contents[last_end..byte_range.start]);
         new_contents.push_str(&new_text);
-        last_end = byte_range.end
+        last_end = byte_range.end;
+
+        replacements.push(Replacement {
+            old_text: file_contents[byte_range.clone()].to_owned(),
+            byte_range,
+            new_text: new_text.into_owned(),
+        });
     }
 
     new_contents.push_str(&file_contents[last_end..]);
 
-    Some(new_contents)
+    Some(RenamedContents { contents: new_contents, replacements, dropped_replacements, errors: vec![] })
 }
 
-fn validate_rename_refs_in_content(new_file_contents: &str, all_objects: &Vec<&NotionObject>) -> Vec<RenameRefsInFileError> {
+/// Undoes `replacements` in `new_contents`, to get back the contents as they were before.
+/// Returns None if the contents don't match the replacements, for example because the file has been edited since.
+pub fn undo_replacements(new_contents: &str, replacements: &[Replacement]) -> Option<String> {
+    let mut old_contents = String::with_capacity(new_contents.len());
+
+    let mut last_end = 0;
+    // Byte offset between the positions in the old contents and the positions in the new contents
+    let mut offset: isize = 0;
+    for Replacement { byte_range, old_text, new_text } in replacements {
+        let new_start = byte_range.start.checked_add_signed(offset)?;
+        let new_end = new_start + new_text.len();
+        if new_start < last_end || new_contents.get(new_start..new_end)? != new_text {
+            return None;
+        }
+
+        old_contents.push_str(&new_contents[last_end..new_start]);
+        old_contents.push_str(old_text);
+        last_end = new_end;
+        offset += new_text.len() as isize - old_text.len() as isize;
+    }
+
+    old_contents.push_str(new_contents.get(last_end..)?);
+
+    Some(old_contents)
+}
+
+fn validate_rename_refs_in_content(new_file_contents: &str, matcher: &ReferencesMatcher) -> Vec<RenameRefsInFileError> {
     let mut errors = vec![];
-    for obj in all_objects {
+    let mut uuids_reported = HashSet::new();
+    for (uuid_byte_index, obj) in matcher.find_objects_by_uuid(new_file_contents) {
         match obj {
-            NotionObject::Page(obj_info, replace_strings) | NotionObject::Database(obj_info, _, replace_strings) => {
-                if let Some(uuid_byte_index) = new_file_contents.find(obj_info.uuid.as_str()) {
-                    // UUID found! raise error
-
-                    let window_byte_offset = max(30, obj_info.name.len() * 2); // Arbitrary offset, to accomodate at least more than 1 instance of the name.
-                    let uuid_size = obj_info.uuid.len();
-                    let window_where_uuid_appears_begin = if uuid_byte_index > window_byte_offset {
-                        new_file_contents.floor_char_boundary(uuid_byte_index - window_byte_offset)
-                    } else {
-                        0
-                    };
-                    let window_where_uuid_appears_end = new_file_contents.ceil_char_boundary(uuid_byte_index + uuid_size + window_byte_offset);
-                    let window_where_uuid_appears = &new_file_contents[window_where_uuid_appears_begin..window_where_uuid_appears_end];
-
-                    errors.push(RenameRefsInFileError::RefRemainingInFile {
-                        uuid: obj_info.uuid.clone(),
-                        new_name: obj_info.new_name.to_owned().unwrap_or_default(),
-                        // window does not need to be precise
-                        window_where_uuid_appears: window_where_uuid_appears.to_owned(),
-                        looked_for: match replace_strings {
-                            Some(replace_strings_container) => {
-                                replace_strings_container.global_references
-                                    .iter()
-                                    .map(|c| c.old_ref.clone())
-                                    .collect()
-                            },
-                            None => vec![]
-                        },
-                    });
+            NotionObject::Page(obj_info, replace_strings)
+            | NotionObject::Database(obj_info, _, replace_strings)
+            | NotionObject::Cleaned(obj_info, replace_strings) => {
+                // Only the first occurrence of each UUID is reported
+                if !uuids_reported.insert(obj_info.uuid.as_str()) {
+                    continue;
                 }
+
+                // UUID found! raise error
+                let window_byte_offset = max(30, obj_info.name.len() * 2); // Arbitrary offset, to accomodate at least more than 1 instance of the name.
+                let uuid_size = obj_info.uuid.len();
+                let window_where_uuid_appears_begin = if uuid_byte_index > window_byte_offset {
+                    new_file_contents.floor_char_boundary(uuid_byte_index - window_byte_offset)
+                } else {
+                    0
+                };
+                let window_where_uuid_appears_end = new_file_contents.ceil_char_boundary(uuid_byte_index + uuid_size + window_byte_offset);
+                let window_where_uuid_appears = &new_file_contents[window_where_uuid_appears_begin..window_where_uuid_appears_end];
+
+                errors.push(RenameRefsInFileError::RefRemainingInFile {
+                    uuid: obj_info.uuid.clone(),
+                    new_name: obj_info.new_name.to_owned().unwrap_or_default(),
+                    // window does not need to be precise
+                    window_where_uuid_appears: window_where_uuid_appears.to_owned(),
+                    looked_for: match replace_strings {
+                        Some(replace_strings_container) => {
+                            replace_strings_container.global_references
+                                .iter()
+                                .map(|c| c.old_ref.clone())
+                                .collect()
+                        },
+                        None => vec![]
+                    },
+                });
             },
             _ => continue,
         };
@@ -171,37 +696,441 @@ fn validate_rename_refs_in_content(new_file_contents: &str, all_objects: &Vec<&N
     errors
 }
 
-/// Renames all references to all objects in all given files.
-pub fn rename_refs_in_all_files(all_files: &Vec<&FileType>, all_objects: &Vec<&NotionObject>, is_test: bool) {
-    for file in all_files
+/// Renames all references to all objects in the contents of the file at `path`, and checks the result.
+/// Returns None if there was nothing to replace, and no problem to report.
+pub fn rename_refs_in_file_contents(old_contents: &str, path: &Path, matcher: &ReferencesMatcher) -> Option<RenamedContents> {
+    let (replace_descriptors, dropped_replacements, mut errors) = prepare_rename_refs_in_content(old_contents, path, matcher);
+    let mut renamed_contents = match do_rename_refs_in_content(old_contents, replace_descriptors) {
+        Some(renamed_contents) => renamed_contents,
+        // Nothing to replace, but problems to report
+        None if !errors.is_empty() || !dropped_replacements.is_empty() => RenamedContents {
+            contents: old_contents.to_string(),
+            replacements: vec![],
+            dropped_replacements: vec![],
+            errors: vec![],
+        },
+        None => return None,
+    };
+
+    errors.extend(
+        validate_rename_refs_in_content(&renamed_contents.contents, matcher)
+            .into_iter()
+            .filter(|error| match error {
+                // uuid is expected to appear in index.html. It's not a failing renaming.
+                RenameRefsInFileError::RefRemainingInFile{ .. } => !path.ends_with("index.html"),
+                _ => true,
+            }),
+    );
+    renamed_contents.errors = errors;
+    renamed_contents.dropped_replacements.extend(dropped_replacements);
+    renamed_contents.dropped_replacements.sort_by_key(|dropped| dropped.byte_range.start);
+
+    Some(renamed_contents)
+}
+
+/// Renames all references found by `matcher` in all given files, on all cores.
+/// `write` is called, from any thread, with the new contents of each file that has references to rename.
+/// Its first error stops the renaming.
+/// Then `on_renamed` is called for each of these files, and the files with problems to report, in the order of their paths.
+/// Its `RenamedContents` don't have their contents anymore, they were moved to `write`.
+/// Files that can't be read are left untouched, and their errors returned, in the order of their paths.
+pub fn rename_refs_in_all_files(
+    all_files: &Vec<&FileType>,
+    matcher: &ReferencesMatcher,
+    write: impl Fn(&PathBuf, String) -> Result<(), RewriteError> + Sync,
+    mut on_renamed: impl FnMut(&PathBuf, RenamedContents),
+) -> Result<Vec<RewriteError>, RewriteError> {
+    // Sorted, so that the results don't depend on the order of the files
+    let mut paths = all_files
         .iter()
-        .progress_with_style(PROGRESS_BAR_STYLE.clone())
         .filter(|ft| ft.is_readable_type())
-    {
-        let path = file.get_path();
-
-        let old_contents = fs::read_to_string(path).unwrap(); // Should not panic, file should be readable
-        let replace_descriptors = prepare_rename_refs_in_content(&old_contents, path, all_objects);
-        if let Some(new_content) = do_rename_refs_in_content(&old_contents, replace_descriptors) {
-            
-            let errors_encountered = validate_rename_refs_in_content(&new_content, all_objects);
-    
-            for error in errors_encountered {
-                match error {
-                    RenameRefsInFileError::RefRemainingInFile{ .. } => {
-                        if path.ends_with("index.html") {
-                            // uuid is expected to appear in index.html. It's not a failing renaming.
-                            continue;
-                        }
-    
-                        println!("Warning: non-fatal problem found while renaming references in {:?}:\n\t{}", path, error);
-                    }
-                }
-            }
-    
-            if !is_test {
-                fs::write(path,new_content).unwrap(); // Should not panic, file should be writable
+        .map(|ft| ft.get_path())
+        .collect::<Vec<&PathBuf>>();
+    paths.sort();
+
+    // The outer result is the error that stops the renaming, the inner one is the error of a file left untouched
+    let results = paths
+        .par_iter()
+        .progress_with_style(PROGRESS_BAR_STYLE.clone())
+        .map(|path| -> Result<Result<Option<RenamedContents>, RewriteError>, RewriteError> {
+            let old_contents = match fs::read_to_string(path) {
+                Ok(old_contents) => old_contents,
+                Err(source) => return Ok(Err(RewriteError::Read { path: path.to_path_buf(), source })),
+            };
+
+            let Some(mut renamed_contents) = rename_refs_in_file_contents(&old_contents, path, matcher) else {
+                return Ok(Ok(None));
+            };
+            if !renamed_contents.replacements.is_empty() {
+                write(path, std::mem::take(&mut renamed_contents.contents))?;
             }
+            Ok(Ok(Some(renamed_contents)))
+        })
+        .collect::<Result<Vec<_>, RewriteError>>()?;
+
+    let mut errors = vec![];
+    for (path, result) in paths.into_iter().zip(results) {
+        match result {
+            Ok(Some(renamed_contents)) => on_renamed(path, renamed_contents),
+            Ok(None) => {}
+            Err(e) => errors.push(e),
+        }
+    }
+
+    Ok(errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::notion_object::NotionObjectInfo;
+
+    use super::*;
+
+    const HOME_UUID: &str = "11111111111111111111111111111111";
+    const CHILD_UUID: &str = "22222222222222222222222222222222";
+
+    fn descriptor(start: usize, end: usize, new_text: &str) -> ReplaceDescriptor<'_> {
+        ReplaceDescriptor::new_from_borrowed(start, end, new_text).unwrap()
+    }
+
+    /// Returns the page `name uuid.extension` in `dir`, renamed to `name`, with its directory if `has_dir`.
+    fn page(dir: &str, name: &str, uuid: &str, extension: &str, has_dir: bool) -> NotionObject {
+        let dir = Path::new(dir);
+        let path = dir.join(format!("{} {}.{}", name, uuid, extension));
+        let dir_path = has_dir.then(|| dir.join(format!("{} {}", name, uuid)));
+        let mut page = NotionObject::Page(NotionObjectInfo::new(path, name.to_string(), uuid.to_string(), dir_path), None);
+        page.accept_new_name(name.to_string());
+        page
+    }
+
+    /// Returns the contents of the file at `path` once its references are renamed.
+    fn rename(contents: &str, path: &str, matcher: &ReferencesMatcher) -> String {
+        rename_refs_in_file_contents(contents, Path::new(path), matcher).map_or(contents.to_string(), |renamed| renamed.contents)
+    }
+
+    #[test]
+    fn nothing_to_replace_gives_nothing() {
+        assert!(do_rename_refs_in_content("Home", vec![]).is_none());
+    }
+
+    #[test]
+    fn replaces_in_order_of_position() {
+        let contents = "See A 1 and B 2.";
+        let renamed = do_rename_refs_in_content(contents, vec![descriptor(12, 15, "B"), descriptor(4, 7, "A")]).unwrap();
+
+        assert_eq!(renamed.contents, "See A and B.");
+        let replaced = renamed.replacements.iter().map(|r| (r.byte_range.clone(), r.old_text.as_str(), r.new_text.as_str())).collect::<Vec<_>>();
+        assert_eq!(replaced, vec![(4..7, "A 1", "A"), (12..15, "B 2", "B")]);
+        assert!(renamed.dropped_replacements.is_empty());
+    }
+
+    #[test]
+    fn drops_overlapping_replacements_keeping_the_longest() {
+        let contents = "Page A 1/Child 2.md";
+        let renamed = do_rename_refs_in_content(
+            contents,
+            vec![descriptor(7, 8, "x"), descriptor(0, 19, "Page A/Child.md"), descriptor(9, 19, "Child.md")],
+        )
+        .unwrap();
+
+        assert_eq!(renamed.contents, "Page A/Child.md");
+        let dropped = renamed.dropped_replacements.iter().map(|r| (r.byte_range.clone(), r.old_text.as_str())).collect::<Vec<_>>();
+        assert_eq!(dropped, vec![(7..8, "1"), (9..19, "Child 2.md")]);
+    }
+
+    #[test]
+    fn does_not_report_the_same_replacement_twice() {
+        let renamed = do_rename_refs_in_content("A 1", vec![descriptor(0, 3, "A"), descriptor(0, 3, "A")]).unwrap();
+
+        assert_eq!(renamed.contents, "A");
+        assert_eq!(renamed.replacements.len(), 1);
+        assert!(renamed.dropped_replacements.is_empty());
+    }
+
+    #[test]
+    fn rewrites_html_links_with_a_dot_in_the_root() {
+        for root in ["./export", ".", "export/./"] {
+            let home = page(root, "Home", HOME_UUID, "html", true);
+            let child = page(&format!("{}/Home {}", root, HOME_UUID), "Child", CHILD_UUID, "html", false);
+            let matcher = ReferencesMatcher::new(&vec![&home, &child]);
+
+            let contents = format!(r#"<a href="Home%20{0}/Child%20{1}.html#x">Child</a>"#, HOME_UUID, CHILD_UUID);
+            let home_path = format!("{}/Home {}.html", root, HOME_UUID);
+            assert_eq!(rename(&contents, &home_path, &matcher), r#"<a href="Home/Child.html#x">Child</a>"#, "root {}", root);
+
+            let contents = format!(r#"<a href="../Home%20{}.html">Home</a>"#, HOME_UUID);
+            let child_path = format!("{}/Home {}/Child {}.html", root, HOME_UUID, CHILD_UUID);
+            assert_eq!(rename(&contents, &child_path, &matcher), r#"<a href="../Home.html">Home</a>"#, "root {}", root);
+        }
+    }
+
+    #[test]
+    fn rewrites_html_links_with_a_colon_in_the_page_name() {
+        let home = page("export", "Home", HOME_UUID, "html", false);
+        let meeting = page("export", "Meeting: Q1", CHILD_UUID, "html", false);
+        let matcher = ReferencesMatcher::new(&vec![&home, &meeting]);
+
+        let contents = format!(r#"<a href="Meeting:%20Q1%20{}.html">Q1</a>"#, CHILD_UUID);
+        assert_eq!(rename(&contents, &format!("export/Home {}.html", HOME_UUID), &matcher), r#"<a href="Meeting:%20Q1.html">Q1</a>"#);
+    }
+
+    #[test]
+    fn leaves_absolute_urls_in_html_as_they_are() {
+        let home = page("export", "Home", HOME_UUID, "html", false);
+        let matcher = ReferencesMatcher::new(&vec![&home]);
+
+        let contents = r#"<a href="mailto:a@example.com">a</a><a href="https://example.com/a:b">b</a><img src="data:image/png;base64,AA==">"#;
+        assert_eq!(rename(contents, &format!("export/Home {}.html", HOME_UUID), &matcher), contents);
+    }
+
+    #[test]
+    fn tells_absolute_urls_from_relative_links() {
+        assert!(is_absolute_url("https://example.com"));
+        assert!(is_absolute_url("custom+scheme://host/path"));
+        assert!(is_absolute_url("mailto:a@example.com"));
+        assert!(is_absolute_url("TEL:+331234"));
+        assert!(!is_absolute_url("Meeting:%20Q1.html"));
+        assert!(!is_absolute_url("Meeting: Q1/Notes.md"));
+        assert!(!is_absolute_url("1:1 notes.md"));
+        assert!(!is_absolute_url("Page.md"));
+        assert!(!is_absolute_url("../a:b.md"));
+    }
+
+    #[test]
+    fn rewrites_notion_links_with_dashed_uuids_to_pages_of_the_export_whatever_the_policy() {
+        let home = page("export", "Home", HOME_UUID, "md", false);
+        let child = page("export", "Child", CHILD_UUID, "md", false);
+        let home_path = format!("export/Home {}.md", HOME_UUID);
+        let contents = "[a](https://www.notion.so/Child-22222222-2222-2222-2222-222222222222) and <https://www.notion.so/22222222-2222-2222-2222-222222222222>";
+
+        for policy in [ExternalLinks::Keep, ExternalLinks::PlainText, ExternalLinks::Placeholder] {
+            let matcher = ReferencesMatcher::new(&vec![&home, &child]).external_links(policy, PathBuf::from("export/Not in this export.md"));
+            let renamed = rename_refs_in_file_contents(contents, Path::new(&home_path), &matcher).unwrap();
+            assert_eq!(renamed.contents, "[a](Child.md) and [Child](Child.md)", "policy {:?}", policy);
+            assert!(renamed.errors.is_empty(), "policy {:?}", policy);
+        }
+    }
+
+    #[test]
+    fn rewrites_notion_links_with_dashed_uuids_to_other_pages_following_the_policy() {
+        let home = page("export", "Home", HOME_UUID, "md", false);
+        let home_path = format!("export/Home {}.md", HOME_UUID);
+        let contents = "[a](https://www.notion.so/Other-33333333-3333-3333-3333-333333333333)";
+
+        let expected = [
+            (ExternalLinks::Keep, contents),
+            (ExternalLinks::PlainText, "a"),
+            (ExternalLinks::Placeholder, "[a](Not%20in%20this%20export.md)"),
+        ];
+        for (policy, expected) in expected {
+            let matcher = ReferencesMatcher::new(&vec![&home]).external_links(policy, PathBuf::from("export/Not in this export.md"));
+            let renamed = rename_refs_in_file_contents(contents, Path::new(&home_path), &matcher).unwrap();
+            assert_eq!(renamed.contents, expected, "policy {:?}", policy);
+            assert!(
+                matches!(renamed.errors.as_slice(), [RenameRefsInFileError::ExternalNotionLink { uuid, .. }] if uuid == "33333333-3333-3333-3333-333333333333"),
+                "policy {:?}",
+                policy
+            );
+        }
+    }
+
+    #[test]
+    fn rewrites_notion_links_with_several_uuids_to_the_page_of_the_last_one() {
+        let home = page("export", "Home", HOME_UUID, "md", false);
+        let child = page("export", "Child", CHILD_UUID, "md", false);
+        let matcher = ReferencesMatcher::new(&vec![&home, &child]);
+        let home_path = format!("export/Home {}.md", HOME_UUID);
+        let contents = format!(
+            "[a](https://www.notion.so/Home-{0}/Child-{1}) [b](https://www.notion.so/Child-{1}/Home-{0}/Child-{1}) [c](https://www.notion.so/Home-{0}/Other-{2})",
+            HOME_UUID, CHILD_UUID, "33333333333333333333333333333333"
+        );
+
+        let renamed = rename_refs_in_file_contents(&contents, Path::new(&home_path), &matcher).unwrap();
+        assert_eq!(
+            renamed.contents,
+            format!("[a](Child.md) [b](Child.md) [c](https://www.notion.so/Home-{}/Other-33333333333333333333333333333333)", HOME_UUID)
+        );
+        // The link to the other page is kept, with the UUID of Home in it
+        assert!(matches!(
+            renamed.errors.as_slice(),
+            [RenameRefsInFileError::ExternalNotionLink { uuid, .. }, RenameRefsInFileError::RefRemainingInFile { .. }] if uuid == "33333333333333333333333333333333"
+        ));
+    }
+
+    #[test]
+    fn rewrites_html_notion_links_with_dashed_uuids() {
+        let home = page("export", "Home", HOME_UUID, "html", false);
+        let child = page("export", "Child", CHILD_UUID, "html", false);
+        let matcher = ReferencesMatcher::new(&vec![&home, &child]).external_links(ExternalLinks::Placeholder, PathBuf::from("export/Not in this export.html"));
+
+        let contents = r#"<a href="https://www.notion.so/22222222-2222-2222-2222-222222222222">a</a>"#;
+        assert_eq!(rename(contents, &format!("export/Home {}.html", HOME_UUID), &matcher), r#"<a href="Child.html">a</a>"#);
+    }
+
+    #[test]
+    fn reads_the_headings_of_a_page_only_when_a_link_points_to_one_of_its_blocks() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let home = page("export", "Home", HOME_UUID, "md", false);
+        let child = page("export", "Child", CHILD_UUID, "md", false);
+        let home_path = format!("export/Home {}.md", HOME_UUID);
+        let reads = AtomicUsize::new(0);
+        let matcher = ReferencesMatcher::new(&vec![&home, &child]).read_pages_with(|path| {
+            assert!(path.ends_with(format!("Child {}.md", CHILD_UUID)));
+            reads.fetch_add(1, Ordering::Relaxed);
+            Ok("# Child\n\n## Next steps\n".to_string())
+        });
+
+        let contents = "[Child](https://www.notion.so/Child-22222222222222222222222222222222)";
+        assert_eq!(rename(contents, &home_path, &matcher), "[Child](Child.md)");
+        assert_eq!(reads.load(Ordering::Relaxed), 0);
+
+        let block = "33333333333333333333333333333333";
+        let contents = format!(
+            "[Next steps](https://www.notion.so/Child-{0}#{1}) [Other](https://www.notion.so/Child-{0}#{1}) [Next steps](https://www.notion.so/Child-{0}#{1})",
+            CHILD_UUID, block
+        );
+        let expected = format!("[Next steps](Child.md#next-steps) [Other](Child.md#{0}) [Next steps](Child.md#next-steps)", block);
+        assert_eq!(rename(&contents, &home_path, &matcher), expected);
+        assert_eq!(reads.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn recognizes_notion_links_by_their_host() {
+        let notion_hosts = ["notion.so", "*.Notion.site", "wiki.example.com"].map(normalize_notion_host);
+        assert!(is_notion_link("https://www.notion.so/Home-11111111111111111111111111111111", &notion_hosts));
+        assert!(is_notion_link("https://acme.notion.site/Home-11111111111111111111111111111111", &notion_hosts));
+        assert!(is_notion_link("HTTPS://WIKI.EXAMPLE.COM:443/Home", &notion_hosts));
+        assert!(is_notion_link("www.notion.so/Home-11111111111111111111111111111111", &notion_hosts));
+
+        assert!(!is_notion_link("https://notnotion.so/Home", &notion_hosts));
+        assert!(!is_notion_link("https://notion.so.example.com/Home", &notion_hosts));
+        assert!(!is_notion_link("https://example.com/notion.so", &notion_hosts));
+    }
+
+    #[test]
+    fn finds_the_title_in_notion_links() {
+        assert_eq!(get_title_from_notion_link("https://www.notion.so/acme/Meeting-Notes-11111111111111111111111111111111?pvs=4"), "Meeting Notes");
+        assert_eq!(get_title_from_notion_link("https://acme.notion.site/R%C3%A9union-11111111-1111-1111-1111-111111111111/"), "Réunion");
+        assert_eq!(get_title_from_notion_link("https://www.notion.so/11111111111111111111111111111111#22222222222222222222222222222222"), "Notion page");
+    }
+
+    #[test]
+    fn rewrites_links_to_custom_notion_hosts_and_workspace_slugs() {
+        let home = page("export", "Home", HOME_UUID, "md", false);
+        let child = page("export", "Child", CHILD_UUID, "md", false);
+        let home_path = format!("export/Home {}.md", HOME_UUID);
+        let contents = format!(
+            "[a](https://wiki.example.com/acme/Child-{0}) [b](https://acme.notion.site/Child-{0}) [c](https://www.notion.so/acme/Child-{0})",
+            CHILD_UUID
+        );
+
+        let matcher = ReferencesMatcher::new(&vec![&home, &child]);
+        let expected = format!("[a](https://wiki.example.com/acme/Child-{}) [b](Child.md) [c](Child.md)", CHILD_UUID);
+        assert_eq!(rename(&contents, &home_path, &matcher), expected);
+
+        let matcher = ReferencesMatcher::new(&vec![&home, &child]).notion_hosts(["*.example.com", "notion.so"]);
+        let expected = format!("[a](Child.md) [b](https://acme.notion.site/Child-{}) [c](Child.md)", CHILD_UUID);
+        assert_eq!(rename(&contents, &home_path, &matcher), expected);
+    }
+
+    #[test]
+    fn replaces_raw_and_encoded_names_in_one_pass() {
+        let home = page("export", "Home", HOME_UUID, "md", false);
+        // Its old name contains the one of `Home`
+        let see_home = page("export", &format!("See Home {}", HOME_UUID), CHILD_UUID, "md", false);
+        let objects = vec![&home, &see_home];
+        let matcher = ReferencesMatcher::new(&objects);
+
+        let contents = format!(
+            "Home {0}.md, Home%20{0}.md, See Home {0} {1}.md, See%20Home%20{0}%20{1}.md",
+            HOME_UUID, CHILD_UUID
+        );
+        let mut descriptors = vec![];
+        prepare_rename_refs_in_text(&mut descriptors, &mut vec![], &contents, Path::new("export/notes.txt"), &matcher);
+        let renamed = do_rename_refs_in_content(&contents, descriptors).unwrap();
+
+        assert_eq!(
+            renamed.contents,
+            format!("Home.md, Home.md, See Home {0}.md, See%20Home%20{0}.md", HOME_UUID)
+        );
+        // The names of `Home` in the names of `See Home` overlap the longer ones
+        let dropped = renamed.dropped_replacements.iter().map(|r| r.old_text.as_str()).collect::<Vec<_>>();
+        let home_name = format!("Home {}", HOME_UUID);
+        let encoded_home_name = format!("Home%20{}", HOME_UUID);
+        assert_eq!(dropped, vec![home_name.as_str(), encoded_home_name.as_str()]);
+    }
+
+    #[test]
+    fn reports_the_files_in_the_order_of_their_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut paths = vec![];
+        // Pages linking to a Notion page that is not part of the export, and files that are not UTF-8
+        for i in (0..20).rev() {
+            let page_path = dir.path().join(format!("Page {:02}.md", i));
+            fs::write(&page_path, format!("[Out](https://www.notion.so/Out-{})", HOME_UUID)).unwrap();
+            let binary_path = dir.path().join(format!("Binary {:02}.md", i));
+            fs::write(&binary_path, [0xff, 0xfe]).unwrap();
+            paths.extend([page_path, binary_path]);
         }
+        let files = paths.into_iter().map(|path| FileType::try_from(path).unwrap()).collect::<Vec<FileType>>();
+        let matcher = ReferencesMatcher::new(&vec![]);
+
+        let mut renamed_paths = vec![];
+        let errors = rename_refs_in_all_files(&files.iter().collect(), &matcher, |_, _| Ok(()), |path, renamed_contents| {
+            assert_eq!(renamed_contents.errors.len(), 1);
+            renamed_paths.push(path.clone());
+        })
+        .unwrap();
+
+        let expected_paths = |prefix: &str| (0..20).map(|i| dir.path().join(format!("{} {:02}.md", prefix, i))).collect::<Vec<PathBuf>>();
+        assert_eq!(renamed_paths, expected_paths("Page"));
+        let error_paths = errors
+            .iter()
+            .map(|error| match error {
+                RewriteError::Read { path, .. } => path.clone(),
+                error => panic!("unexpected error {}", error),
+            })
+            .collect::<Vec<PathBuf>>();
+        assert_eq!(error_paths, expected_paths("Binary"));
+    }
+
+    #[test]
+    fn rewrites_the_relative_links_of_files_placed_in_subdirectories() {
+        let mut row = page("export/Tasks", "Row", HOME_UUID, "md", false);
+        row.accept_new_name("2024/Row".to_string());
+        let other_row = page("export/Tasks", "Other", CHILD_UUID, "md", false);
+        let objects = vec![&row, &other_row];
+        let matcher = ReferencesMatcher::new(&objects);
+
+        let contents = format!("[Other](Other%20{}.md) ![](image.png) [Row](#top) [Web](https://example.com/a.png)", CHILD_UUID);
+        assert_eq!(
+            rename(&contents, &format!("export/Tasks/Row {}.md", HOME_UUID), &matcher),
+            "[Other](../Other.md) ![](../image.png) [Row](#top) [Web](https://example.com/a.png)"
+        );
+        // The files that stay where they are only get the new names
+        let contents = format!("[Row](Row%20{}.md) ![](image.png)", HOME_UUID);
+        assert_eq!(rename(&contents, &format!("export/Tasks/Other {}.md", CHILD_UUID), &matcher), "[Row](2024/Row.md) ![](image.png)");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn finds_pages_whose_files_have_a_dashed_uppercase_uuid() {
+        let home = page("export", "Home", HOME_UUID, "md", false);
+        let key = "Child 2222AAAA-2222-2222-2222-222222222222";
+        let mut child = NotionObject::Page(
+            NotionObjectInfo {
+                old_name: key.to_string(),
+                ..NotionObjectInfo::new(PathBuf::from(format!("export/{}.md", key)), "Child".to_string(), "2222aaaa222222222222222222222222".to_string(), None)
+            },
+            None,
+        );
+        child.accept_new_name("Child".to_string());
+        let matcher = ReferencesMatcher::new(&vec![&home, &child]);
+
+        let contents = concat!(
+            "[a](Child%202222AAAA-2222-2222-2222-222222222222.md) [b](https://www.notion.so/Child-2222aaaa222222222222222222222222) ",
+            "[c](https://www.notion.so/2222AAAA-2222-2222-2222-222222222222)"
+        );
+        assert_eq!(rename(contents, &format!("export/Home {}.md", HOME_UUID), &matcher), "[a](Child.md) [b](Child.md) [c](Child.md)");
+    }
+}
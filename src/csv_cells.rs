@@ -0,0 +1,155 @@
+use std::ops::Range;
+
+use csv_core::{ReadFieldResult, Reader};
+
+/// A cell of a CSV file.
+pub struct CsvCell {
+    /// Where the cell is written in the file, with its quotes if it has some
+    pub range: Range<usize>,
+    /// The contents of the cell, without quotes and escapes
+    pub value: String,
+    pub is_quoted: bool,
+}
+
+impl CsvCell {
+    /// Returns where the byte at `value_index` in the value of the cell is in the file.
+    /// In a quoted cell, the quotes of the value are escaped by doubling them.
+    pub fn get_position_in_file(&self, value_index: usize) -> usize {
+        if !self.is_quoted {
+            return self.range.start + value_index;
+        }
+        let escaped_quotes = self.value[..value_index].matches('"').count();
+        self.range.start + 1 + value_index + escaped_quotes
+    }
+}
+
+/// Returns all cells of the CSV `contents`, in order.
+/// The byte order mark at the beginning of the file, if any, is not part of the first cell.
+pub fn read_cells(contents: &str) -> Vec<CsvCell> {
+    let bytes = contents.as_bytes();
+    let mut reader = Reader::new();
+    let mut cells = vec![];
+
+    // A value is never longer than its cell
+    let mut output = vec![0; bytes.len() + 1];
+    let mut output_len = 0;
+
+    let mut position = contents.strip_prefix('\u{feff}').map_or(0, |_| '\u{feff}'.len_utf8());
+    let mut cell_start = position;
+    loop {
+        let (result, input_len, written_len) = reader.read_field(&bytes[position..], &mut output[output_len..]);
+        position += input_len;
+        output_len += written_len;
+
+        match result {
+            ReadFieldResult::Field { .. } => {
+                // A record can start with the end of the line of the previous one
+                let raw_cell = contents[cell_start..position].trim_start_matches(['\r', '\n']);
+                let cell_start_trimmed = position - raw_cell.len();
+                let is_quoted = raw_cell.starts_with('"');
+                // The end of the cell is its closing quote, or the first separator
+                let cell_len = if is_quoted {
+                    raw_cell.rfind('"').map_or(raw_cell.len(), |quote| quote + 1)
+                } else {
+                    raw_cell.find([',', '\r', '\n']).unwrap_or(raw_cell.len())
+                };
+
+                cells.push(CsvCell {
+                    range: cell_start_trimmed..cell_start_trimmed + cell_len,
+                    value: String::from_utf8_lossy(&output[..output_len]).into_owned(),
+                    is_quoted,
+                });
+                output_len = 0;
+                cell_start = position;
+            }
+            // At the end of the input, the next read ends the last cell
+            ReadFieldResult::InputEmpty => {}
+            ReadFieldResult::OutputFull | ReadFieldResult::End => break,
+        }
+    }
+
+    cells
+}
+
+/// Writes `value` as a CSV cell. It's quoted if `is_quoted`, or if it has to be.
+pub fn write_cell(value: &str, is_quoted: bool) -> String {
+    if is_quoted || value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the text of each cell in `contents`, and its value.
+    fn cells(contents: &str) -> Vec<(&str, String)> {
+        read_cells(contents).into_iter().map(|cell| (&contents[cell.range], cell.value)).collect()
+    }
+
+    #[test]
+    fn reads_unquoted_cells() {
+        assert_eq!(
+            cells("Name,Tags\nHome,a\r\nChild,\n"),
+            vec![("Name", "Name".into()), ("Tags", "Tags".into()), ("Home", "Home".into()), ("a", "a".into()), ("Child", "Child".into()), ("", "".into())],
+        );
+    }
+
+    #[test]
+    fn reads_quoted_cells() {
+        let contents = "\"a, b\",\"say \"\"hi\"\"\",\"multi\nline\"";
+        assert_eq!(
+            cells(contents),
+            vec![("\"a, b\"", "a, b".into()), ("\"say \"\"hi\"\"\"", "say \"hi\"".into()), ("\"multi\nline\"", "multi\nline".into())],
+        );
+        assert!(read_cells(contents).iter().all(|cell| cell.is_quoted));
+    }
+
+    #[test]
+    fn skips_the_byte_order_mark() {
+        let contents = "\u{feff}Name,\"Link\"";
+        assert_eq!(cells(contents), vec![("Name", "Name".into()), ("\"Link\"", "Link".into())]);
+    }
+
+    #[test]
+    fn reads_multi_byte_values() {
+        assert_eq!(cells("été,\"😀\""), vec![("été", "été".into()), ("\"😀\"", "😀".into())]);
+    }
+
+    #[test]
+    fn finds_the_positions_of_values_in_the_file() {
+        let contents = "x,\"say \"\"hi\"\" Page\"";
+        let cell = read_cells(contents).remove(1);
+        let start = cell.value.find("Page").unwrap();
+        let range = cell.get_position_in_file(start)..cell.get_position_in_file(start + "Page".len());
+        assert_eq!(&contents[range], "Page");
+
+        let cell = read_cells(contents).remove(0);
+        assert_eq!(cell.get_position_in_file(0), 0);
+    }
+
+    #[test]
+    fn writes_cells_quoted_when_needed() {
+        assert_eq!(write_cell("Home", false), "Home");
+        assert_eq!(write_cell("Home", true), "\"Home\"");
+        assert_eq!(write_cell("a, b", false), "\"a, b\"");
+        assert_eq!(write_cell("say \"hi\"", false), "\"say \"\"hi\"\"\"");
+        assert_eq!(write_cell("multi\nline", false), "\"multi\nline\"");
+    }
+
+    #[test]
+    fn written_cells_are_read_back() {
+        for value in ["Home", "a, b", "say \"hi\"", "multi\r\nline", ""] {
+            for is_quoted in [false, true] {
+                let written = write_cell(value, is_quoted);
+                // An empty file has no cell
+                if written.is_empty() {
+                    continue;
+                }
+                assert_eq!(cells(&written), vec![(written.as_str(), value.to_string())]);
+            }
+        }
+    }
+}
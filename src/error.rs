@@ -0,0 +1,117 @@
+use std::{io, path::PathBuf};
+
+pub use crate::content_replacing::RenameRefsInFileError;
+
+/// Errors while walking the input and finding the type of each entry.
+#[derive(Debug, thiserror::Error)]
+pub enum DiscoveryError {
+    #[error("The input {0:?} does not exist")]
+    InputNotFound(PathBuf),
+    #[error("Could not walk the input directory: {0}")]
+    Walk(#[from] walkdir::Error),
+    #[error("Could not read archive {path:?}: {source}")]
+    Archive {
+        path: PathBuf,
+        source: zip::result::ZipError,
+    },
+    #[error("Could not read {path:?}: {source}")]
+    Read { path: PathBuf, source: io::Error },
+    #[error("The path {0:?} is not valid UTF-8")]
+    NonUtf8Path(PathBuf),
+}
+
+/// Errors while building the objects from the files and choosing their new names.
+#[derive(Debug, thiserror::Error)]
+pub enum PlanningError {
+    #[error("Invalid path combination with key [{key}]: {paths:?}")]
+    InvalidPathCombination { key: String, paths: Vec<PathBuf> },
+    #[error("The database [{key}] has a csv_all file {csv_all_path:?} and a directory {dir_path:?}, but no csv file nor page: it's left as it is. Remove the `_all` at the end of the csv_all file to have it renamed.")]
+    CsvAllWithoutCsv {
+        key: String,
+        csv_all_path: PathBuf,
+        dir_path: PathBuf,
+    },
+    #[error("Could not read the manifest {path:?}: {source}")]
+    Manifest { path: PathBuf, source: io::Error },
+}
+
+/// Errors in a name template, see `NameTemplate`.
+#[derive(Debug, thiserror::Error)]
+pub enum TemplateError {
+    #[error("Unknown placeholder {{{0}}} in the name template")]
+    UnknownPlaceholder(String),
+    #[error("A {{ is not closed in the name template, write {{{{ for a literal {{")]
+    Unclosed,
+    #[error("A }} is not opened in the name template, write }}}} for a literal }}")]
+    Unopened,
+    #[error("Invalid date format {0:?} in the name template")]
+    InvalidDateFormat(String),
+    #[error("The name template can't contain {0:?}: write `/` to place pages in subdirectories, like `{{parent}}/{{name}}`")]
+    PathSeparator(char),
+    #[error("The date format {format:?} in the name template gives {separator:?}, which can't be in a file name: write `%Y-%m-%d` rather than `%D`, or `{{created:%Y}}/{{created:%m}}` for subdirectories")]
+    DateWithPathSeparator { format: String, separator: char },
+    #[error("The name template gives the directory {0:?}: pages can only be placed in subdirectories of the directory they are in")]
+    RelativeDirectory(String),
+}
+
+/// Errors while renaming the references in the contents of the files.
+#[derive(Debug, thiserror::Error)]
+pub enum RewriteError {
+    #[error("Could not read {path:?}: {source}")]
+    Read { path: PathBuf, source: io::Error },
+    #[error("Could not write {path:?}: {source}")]
+    Write { path: PathBuf, source: io::Error },
+    #[error("Could not stage the new contents of {path:?}: {source}")]
+    Stage { path: PathBuf, source: io::Error },
+}
+
+/// Errors while renaming the files and directories.
+#[derive(Debug, thiserror::Error)]
+pub enum RenameError {
+    #[error("Could not create the journal: {0}")]
+    CreateJournal(io::Error),
+    #[error("Could not read the journal of the interrupted run: {0}")]
+    ReadJournal(io::Error),
+    #[error("Could not apply the modifications, they must be resumed or rolled back: {0}")]
+    Apply(io::Error),
+    #[error("Could not resume the interrupted run: {0}")]
+    Resume(io::Error),
+    #[error("Could not roll back the interrupted run: {0}")]
+    Rollback(io::Error),
+    #[error("Could not prepare the restoration of the original export: {0}")]
+    StageUndo(io::Error),
+}
+
+/// Any error that can happen while cleaning an export, or undoing a cleaning.
+#[derive(Debug, thiserror::Error)]
+pub enum CleanError {
+    #[error(transparent)]
+    Discovery(#[from] DiscoveryError),
+    #[error(transparent)]
+    Planning(#[from] PlanningError),
+    #[error(transparent)]
+    Rewrite(#[from] RewriteError),
+    #[error(transparent)]
+    Rename(#[from] RenameError),
+    #[error(transparent)]
+    Template(#[from] TemplateError),
+    #[error("A previous run was interrupted while modifying {0:?}. It must be resumed or rolled back first.")]
+    InterruptedRun(PathBuf),
+    #[error("There is no interrupted run to resume or roll back in {0:?}")]
+    NoInterruptedRun(PathBuf),
+    #[error("Output directory {0:?} already exists and is not empty")]
+    OutputDirNotEmpty(PathBuf),
+    #[error("Output directory {0:?} already exists and is not a directory")]
+    OutputDirNotADirectory(PathBuf),
+    #[error("Output directory {output_dir:?} can't be inside the input directory {input_dir:?}")]
+    OutputDirInInput {
+        output_dir: PathBuf,
+        input_dir: PathBuf,
+    },
+    #[error("{0:?} has not been cleaned by this tool, or its undo record has been removed: it can't be undone")]
+    NotCleaned(PathBuf),
+    #[error("{0:?} is not a directory: only an unzipped export can be verified")]
+    NotADirectory(PathBuf),
+    #[error("Could not read or write {path:?}: {source}")]
+    Io { path: PathBuf, source: io::Error },
+}
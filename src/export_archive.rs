@@ -0,0 +1,212 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::File,
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use zip::{result::ZipResult, ZipArchive};
+
+use crate::{constants::EXPORT_PART_REGEX, export_source::ExportSource};
+
+/// ExportArchive is a Notion export read directly from its zip file, without unpacking it first.
+/// Big exports are split by Notion in several `Export-...-Part-N.zip` archives, themselves wrapped in the outer zip.
+/// Other zip files, like the ones attached to pages, are files of the export.
+/// These nested archives are copied to anonymous temporary files, so that all entries are read the same way.
+/// The system removes these files once they are closed, and no other process can open them by their name.
+/// The paths of the entries are relative to the root of the export.
+pub struct ExportArchive {
+    /// Each archive is read by one thread at a time.
+    archives: Vec<Mutex<ZipArchive<File>>>,
+    /// Path of each file -> (index of the archive, index of the entry in that archive)
+    files: BTreeMap<PathBuf, (usize, usize)>,
+    /// Zips don't always have entries for directories, so they are also deduced from the files paths.
+    directories: BTreeSet<PathBuf>,
+}
+
+impl ExportArchive {
+    pub fn open(zip_path: &Path) -> ZipResult<Self> {
+        let mut outer_archive = ZipArchive::new(File::open(zip_path)?)?;
+
+        // A nested archive is a part of the export at the root of the outer archive.
+        // Other zip files are attachments of the pages, and are kept as they are.
+        let nested_archives_indices = (0..outer_archive.len())
+            .filter(|&i| outer_archive.name_for_index(i).is_some_and(|name| EXPORT_PART_REGEX.is_match(name)))
+            .collect::<Vec<usize>>();
+
+        let mut archives = vec![];
+        let mut files = BTreeMap::new();
+        let mut directories = BTreeSet::new();
+
+        for i in nested_archives_indices.iter() {
+            let mut temp_file = tempfile::tempfile()?;
+            io::copy(&mut outer_archive.by_index(*i)?, &mut temp_file)?;
+            temp_file.seek(SeekFrom::Start(0))?;
+
+            archives.push(ZipArchive::new(temp_file)?);
+        }
+
+        let outer_archive_index = archives.len();
+        archives.push(outer_archive);
+
+        for (archive_index, archive) in archives.iter_mut().enumerate() {
+            for entry_index in 0..archive.len() {
+                if archive_index == outer_archive_index && nested_archives_indices.contains(&entry_index) {
+                    continue;
+                }
+
+                let entry = archive.by_index_raw(entry_index)?;
+                // Entries trying to escape the export root are ignored
+                let Some(path) = entry.enclosed_name() else {
+                    continue;
+                };
+
+                let parents = path
+                    .ancestors()
+                    .skip(1)
+                    .filter(|p| !p.as_os_str().is_empty())
+                    .map(|p| p.to_path_buf());
+                directories.extend(parents);
+
+                if entry.is_dir() {
+                    directories.insert(path);
+                } else {
+                    files.insert(path, (archive_index, entry_index));
+                }
+            }
+        }
+
+        Ok(Self {
+            archives: archives.into_iter().map(Mutex::new).collect(),
+            files,
+            directories,
+        })
+    }
+
+    /// Lists all directories and files of the export, and whether they are directories.
+    pub fn entries(&self) -> Vec<(PathBuf, bool)> {
+        self.directories
+            .iter()
+            .map(|dir| (dir.clone(), true))
+            .chain(self.files.keys().map(|file| (file.clone(), false)))
+            .collect()
+    }
+
+    /// Calls `read` with the entry of the file at `path`. Its archive is locked meanwhile.
+    fn read_file<T>(&self, path: &Path, read: impl FnOnce(&mut dyn Read) -> io::Result<T>) -> io::Result<T> {
+        let (archive_index, entry_index) = *self.files.get(path).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{:?} is not in the archive", path),
+            )
+        })?;
+        let mut archive = self.archives[archive_index].lock().unwrap(); // Should not panic, no thread panics while holding the lock
+        let mut entry = archive.by_index(entry_index)?;
+        read(&mut entry)
+    }
+}
+
+impl ExportSource for ExportArchive {
+    /// Paths of the archive entries are relative.
+    fn get_root(&self) -> &Path {
+        Path::new("")
+    }
+
+    fn get_directories(&self) -> Vec<PathBuf> {
+        self.directories.iter().cloned().collect()
+    }
+
+    fn get_file_paths(&self) -> Vec<PathBuf> {
+        self.files.keys().cloned().collect()
+    }
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        self.read_file(path, |entry| {
+            let mut contents = String::new();
+            entry.read_to_string(&mut contents)?;
+            Ok(contents)
+        })
+    }
+
+    fn copy_to(&self, path: &Path, writer: &mut impl Write) -> io::Result<u64> {
+        self.read_file(path, |entry| io::copy(entry, writer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zip::{write::SimpleFileOptions, ZipWriter};
+
+    use super::*;
+
+    /// Returns a zip archive with `files`, by path, and `directories`.
+    fn zip_bytes(files: &[(&str, &[u8])], directories: &[&str]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(io::Cursor::new(vec![]));
+        for directory in directories {
+            writer.add_directory(*directory, SimpleFileOptions::default()).unwrap();
+        }
+        for (path, contents) in files {
+            writer.start_file(*path, SimpleFileOptions::default()).unwrap();
+            writer.write_all(contents).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    /// Writes the zip archive of `zip_bytes` to a temporary directory, and opens it.
+    fn open(bytes: &[u8]) -> ExportArchive {
+        let dir = tempfile::tempdir().unwrap();
+        let zip_path = dir.path().join("Export-0123.zip");
+        std::fs::write(&zip_path, bytes).unwrap();
+        ExportArchive::open(&zip_path).unwrap()
+    }
+
+    fn entries(archive: &ExportArchive) -> Vec<(String, bool)> {
+        archive.entries().into_iter().map(|(path, is_dir)| (path.to_string_lossy().replace('\\', "/"), is_dir)).collect()
+    }
+
+    #[test]
+    fn reads_a_single_archive() {
+        let archive = open(&zip_bytes(&[("Home 1.md", b"# Home"), ("Home 1/Child 2.md", b"# Child")], &["Empty/"]));
+
+        assert_eq!(
+            entries(&archive),
+            vec![("Empty".to_string(), true), ("Home 1".to_string(), true), ("Home 1/Child 2.md".to_string(), false), ("Home 1.md".to_string(), false)]
+        );
+        assert_eq!(archive.read_to_string(Path::new("Home 1/Child 2.md")).unwrap(), "# Child");
+        assert_eq!(archive.read_to_string(Path::new("Missing.md")).unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn reads_the_nested_parts_of_an_export() {
+        let part_1 = zip_bytes(&[("Home 1.md", b"# Home")], &[]);
+        let part_2 = zip_bytes(&[("Home 1/Child 2.md", b"# Child")], &[]);
+        let archive = open(&zip_bytes(&[("Export-0123-Part-1.zip", &part_1), ("Export-0123-Part-2.zip", &part_2)], &[]));
+
+        assert_eq!(
+            entries(&archive),
+            vec![("Home 1".to_string(), true), ("Home 1/Child 2.md".to_string(), false), ("Home 1.md".to_string(), false)]
+        );
+        assert_eq!(archive.read_to_string(Path::new("Home 1.md")).unwrap(), "# Home");
+        assert_eq!(archive.read_to_string(Path::new("Home 1/Child 2.md")).unwrap(), "# Child");
+    }
+
+    #[test]
+    fn keeps_other_zip_files_as_they_are() {
+        let attachment = zip_bytes(&[("inside.txt", b"inside")], &[]);
+        let archive = open(&zip_bytes(&[("Home 1.md", b"# Home"), ("attachment.zip", &attachment), ("Home 1/Part-1.zip", &attachment)], &[]));
+
+        assert_eq!(
+            entries(&archive),
+            vec![
+                ("Home 1".to_string(), true),
+                ("Home 1/Part-1.zip".to_string(), false),
+                ("Home 1.md".to_string(), false),
+                ("attachment.zip".to_string(), false),
+            ]
+        );
+        let mut copied = vec![];
+        archive.copy_to(Path::new("attachment.zip"), &mut copied).unwrap();
+        assert_eq!(copied, attachment);
+    }
+}
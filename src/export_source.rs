@@ -0,0 +1,103 @@
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use walkdir::{DirEntry, WalkDir};
+
+use crate::constants::{JOURNAL_DIR_NAME, UNDO_RECORD_FILE_NAME};
+
+/// ExportSource is where the original export is read from, when the cleaned export is written to another directory.
+/// The paths are the same as the ones of the `FileType`s built from this source.
+/// Files can be read from several threads at once.
+pub trait ExportSource: Sync {
+    /// The root of the export. All paths of the source start with it.
+    fn get_root(&self) -> &Path;
+
+    fn get_directories(&self) -> Vec<PathBuf>;
+
+    fn get_file_paths(&self) -> Vec<PathBuf>;
+
+    /// All directories and files of the source, ignored ones included.
+    fn get_all_paths(&self) -> Vec<PathBuf> {
+        self.get_directories().into_iter().chain(self.get_file_paths()).collect()
+    }
+
+    /// Behaves like `fs::read_to_string`: fails with `InvalidData` if the file isn't valid UTF-8.
+    fn read_to_string(&self, path: &Path) -> io::Result<String>;
+
+    /// Copies the content of the file to `writer`, without loading it entirely in memory.
+    fn copy_to(&self, path: &Path, writer: &mut impl Write) -> io::Result<u64>;
+}
+
+/// An export that has already been unzipped. It is only read, never modified.
+pub struct ExportDirectory {
+    root: PathBuf,
+    directories: Vec<PathBuf>,
+    files: Vec<PathBuf>,
+}
+
+impl ExportDirectory {
+    /// Entries that can't be walked are left out: they are reported by `file_type::build_file_map`.
+    /// The journal and the undo record of this tool are left out too: they describe this directory, not a copy of it.
+    pub fn new(root: PathBuf) -> Self {
+        let mut directories = vec![];
+        let mut files = vec![];
+
+        let is_tool_owned = |entry: &DirEntry| entry.depth() == 1 && (entry.file_name() == JOURNAL_DIR_NAME || entry.file_name() == UNDO_RECORD_FILE_NAME);
+        let entries = WalkDir::new(&root).min_depth(1).sort_by_file_name().into_iter().filter_entry(|entry| !is_tool_owned(entry));
+        for entry in entries.filter_map(Result::ok) {
+            if entry.file_type().is_dir() {
+                directories.push(entry.into_path());
+            } else {
+                files.push(entry.into_path());
+            }
+        }
+
+        Self { root, directories, files }
+    }
+}
+
+impl ExportSource for ExportDirectory {
+    fn get_root(&self) -> &Path {
+        &self.root
+    }
+
+    fn get_directories(&self) -> Vec<PathBuf> {
+        self.directories.clone()
+    }
+
+    fn get_file_paths(&self) -> Vec<PathBuf> {
+        self.files.clone()
+    }
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        fs::read_to_string(path)
+    }
+
+    fn copy_to(&self, path: &Path, writer: &mut impl Write) -> io::Result<u64> {
+        io::copy(&mut File::open(path)?, writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leaves_out_the_journal_and_the_undo_record() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join(JOURNAL_DIR_NAME).join("staged")).unwrap();
+        fs::write(dir.path().join(JOURNAL_DIR_NAME).join("journal.json"), "{}").unwrap();
+        fs::write(dir.path().join(UNDO_RECORD_FILE_NAME), "{}").unwrap();
+        fs::create_dir(dir.path().join("Home")).unwrap();
+        fs::write(dir.path().join("Home.md"), "# Home").unwrap();
+        // Only the entries of this tool at the root are left out
+        fs::write(dir.path().join("Home").join(UNDO_RECORD_FILE_NAME), "{}").unwrap();
+
+        let source = ExportDirectory::new(dir.path().to_path_buf());
+        assert_eq!(source.get_directories(), vec![dir.path().join("Home")]);
+        assert_eq!(source.get_file_paths(), vec![dir.path().join("Home").join(UNDO_RECORD_FILE_NAME), dir.path().join("Home.md")]);
+    }
+}
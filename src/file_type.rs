@@ -1,7 +1,77 @@
-use std::{collections::HashMap, fs, path::PathBuf};
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use walkdir::WalkDir;
+
+use crate::{error::DiscoveryError, export_archive::ExportArchive, export_source::ExportSource};
 
 pub type FileMapByName = HashMap<String, Vec<FileType>>;
 
+/// The file map is a map of file keys to a list of the entries matching this key.
+/// See `FileType::get_file_key` for more information.
+/// Entries with a component in `ignore` are left out.
+/// Entries that can't be read are left out too, and returned as errors.
+pub fn build_file_map(directory_path: &Path, ignore: &HashSet<&str>) -> (FileMapByName, Vec<DiscoveryError>) {
+    let mut file_map: FileMapByName = HashMap::new();
+    let mut errors = vec![];
+
+    for entry in WalkDir::new(directory_path) {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                errors.push(DiscoveryError::from(e));
+                continue;
+            }
+        };
+
+        if is_ignored(entry.path(), ignore) {
+            continue;
+        }
+
+        match FileType::try_from(entry.into_path()) {
+            Ok(ft) => {
+                let file_key = ft.get_file_key().to_string();
+                file_map.entry(file_key).or_default().push(ft);
+            }
+            Err(e) => errors.push(e),
+        }
+    }
+
+    (file_map, errors)
+}
+
+/// Same as `build_file_map`, for the entries of a zip archive.
+pub fn build_file_map_from_archive(archive: &ExportArchive, ignore: &HashSet<&str>) -> (FileMapByName, Vec<DiscoveryError>) {
+    let mut file_map: FileMapByName = HashMap::new();
+    let mut errors = vec![];
+
+    for (path, is_dir) in archive.entries() {
+        if is_ignored(&path, ignore) {
+            continue;
+        }
+
+        match FileType::from_entry(path.clone(), is_dir, || archive.read_to_string(&path)) {
+            Ok(ft) => {
+                let file_key = ft.get_file_key().to_string();
+                file_map.entry(file_key).or_default().push(ft);
+            }
+            Err(e) => errors.push(e),
+        }
+    }
+
+    (file_map, errors)
+}
+
+/// Is one of the components of the path in the ignore list?
+pub fn is_ignored(path: &Path, ignore: &HashSet<&str>) -> bool {
+    path.components()
+        .filter_map(|c| c.as_os_str().to_str())
+        .any(|c| ignore.contains(c))
+}
+
 /// Stores the path and name + UUID of a file or directory.
 #[derive(Debug)]
 pub struct FileInfo {
@@ -63,21 +133,27 @@ impl FileType {
     }
 }
 
-impl From<PathBuf> for FileType {
-    fn from(path: PathBuf) -> Self {
-        let name_uuid = path.file_stem().unwrap().to_str().unwrap();
+impl FileType {
+    /// Builds a FileType from an entry of the export, be it a real file or an entry of a zip archive.
+    /// `read_text` is only called for files with an unsupported extension (or no extension), to know if they can be read as text.
+    pub fn from_entry(path: PathBuf, is_dir: bool, read_text: impl FnOnce() -> io::Result<String>) -> Result<Self, DiscoveryError> {
+        let Some(path_str) = path.to_str() else {
+            return Err(DiscoveryError::NonUtf8Path(path));
+        };
+        // The root of a walk (like "." or "/") has no file name, its key doesn't matter
+        let name_uuid = Path::new(path_str).file_stem().and_then(|stem| stem.to_str()).unwrap_or_default();
         let file_info = FileInfo {
             path: path.clone(),
             name_uuid: name_uuid.to_string(),
         };
 
-        if path.is_dir() {
-            return FileType::Dir(file_info);
+        if is_dir {
+            return Ok(FileType::Dir(file_info));
         }
 
-        let extension = path.extension().unwrap().to_str().unwrap();
+        let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or_default();
 
-        match extension {
+        let file_type = match extension {
             "md" => FileType::Markdown(file_info),
             "html" => FileType::Html(file_info),
             "csv" => {
@@ -96,14 +172,25 @@ impl From<PathBuf> for FileType {
             }
             // The file is not one of the supported extension. We will try to read it as a text file.
             // Because if it is a text file, renaming links inside it may be necessary.
-            _ => match fs::read_to_string(path) {
+            _ => match read_text() {
                 Ok(_) => FileType::OtherTxt(file_info.path),
-                Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+                Err(e) if e.kind() == io::ErrorKind::InvalidData => {
                     // This file is not readable.
                     FileType::OtherBin(file_info.path)
                 }
-                Err(e) => panic!("Unexpected error reading file: {}", e),
+                Err(source) => return Err(DiscoveryError::Read { path, source }),
             },
-        }
+        };
+
+        Ok(file_type)
+    }
+}
+
+impl TryFrom<PathBuf> for FileType {
+    type Error = DiscoveryError;
+
+    fn try_from(path: PathBuf) -> Result<Self, Self::Error> {
+        let is_dir = path.is_dir();
+        FileType::from_entry(path.clone(), is_dir, || fs::read_to_string(path))
     }
 }
@@ -0,0 +1,254 @@
+use std::ops::Range;
+
+/// Attributes whose value is a URL.
+const URL_ATTRIBUTES: [&str; 10] = ["href", "src", "poster", "data", "action", "formaction", "cite", "background", "longdesc", "xlink:href"];
+/// Attribute whose value is a list of URLs with their descriptors, like `image.png 1x, image@2x.png 2x`.
+const SRCSET_ATTRIBUTE: &str = "srcset";
+/// Elements whose contents are text, not tags.
+const TEXT_ELEMENTS: [&str; 6] = ["script", "style", "textarea", "title", "xmp", "noscript"];
+
+/// A URL in a link attribute of an HTML file.
+pub struct HtmlLink {
+    /// The URL as it is written: it may have character references, like `&amp;`
+    pub range: Range<usize>,
+    /// The whole attribute, with its name and the spaces before it
+    pub attribute_range: Range<usize>,
+}
+
+/// Returns the URLs in the link attributes (`href`, `src`, `srcset`...) of the HTML `contents`.
+/// Comments, and the contents of scripts and styles, don't have links.
+/// The URLs are sorted.
+pub fn find_link_attribute_values(contents: &str) -> Vec<HtmlLink> {
+    scan(contents).0
+}
+
+/// Returns the ranges of the text of the HTML `contents`, outside of the tags.
+/// Comments, and the contents of the elements whose contents are not tags, like scripts and styles, are left out.
+/// The ranges are sorted.
+pub fn find_text_ranges(contents: &str) -> Vec<Range<usize>> {
+    scan(contents).1
+}
+
+/// Returns the URLs in the link attributes of the HTML `contents`, and the ranges of its text.
+fn scan(contents: &str) -> (Vec<HtmlLink>, Vec<Range<usize>>) {
+    let bytes = contents.as_bytes();
+    let mut urls = vec![];
+    let mut text_ranges = vec![];
+
+    let mut position = 0;
+    let mut text_start = 0;
+    while let Some(found) = contents[position..].find('<') {
+        let tag_start = position + found;
+        let rest = &contents[tag_start..];
+
+        let tag_end = if rest.starts_with("<!--") {
+            rest.find("-->").map_or(contents.len(), |end| tag_start + end + "-->".len())
+        } else if rest.starts_with("<!") || rest.starts_with("<?") || rest.starts_with("</") {
+            rest.find('>').map_or(contents.len(), |end| tag_start + end + 1)
+        } else if bytes.get(tag_start + 1).is_some_and(u8::is_ascii_alphabetic) {
+            let (tag_name, tag_end) = read_start_tag(contents, tag_start + 1, &mut urls);
+            match TEXT_ELEMENTS.iter().find(|element| element.eq_ignore_ascii_case(tag_name)) {
+                Some(element) => find_end_tag(contents, tag_end, element),
+                None => tag_end,
+            }
+        } else {
+            // A `<` that doesn't start a tag is text
+            position = tag_start + 1;
+            continue;
+        };
+
+        if text_start < tag_start {
+            text_ranges.push(text_start..tag_start);
+        }
+        position = tag_end;
+        text_start = tag_end;
+    }
+    if text_start < contents.len() {
+        text_ranges.push(text_start..contents.len());
+    }
+
+    (urls, text_ranges)
+}
+
+/// Reads the start tag whose name begins at `start`, and adds the URLs of its link attributes to `urls`.
+/// Returns the name of the tag, and the position after it.
+fn read_start_tag<'c>(contents: &'c str, start: usize, urls: &mut Vec<HtmlLink>) -> (&'c str, usize) {
+    let bytes = contents.as_bytes();
+    let is_name_end = |b: u8| b.is_ascii_whitespace() || b == b'/' || b == b'>';
+
+    let mut position = start;
+    while position < bytes.len() && !is_name_end(bytes[position]) {
+        position += 1;
+    }
+    let tag_name = &contents[start..position];
+
+    loop {
+        let attribute_start = position;
+        while position < bytes.len() && (bytes[position].is_ascii_whitespace() || bytes[position] == b'/') {
+            position += 1;
+        }
+        if position >= bytes.len() {
+            return (tag_name, position);
+        }
+        if bytes[position] == b'>' {
+            return (tag_name, position + 1);
+        }
+
+        let name_start = position;
+        while position < bytes.len() && !is_name_end(bytes[position]) && bytes[position] != b'=' {
+            position += 1;
+        }
+        let attribute_name = &contents[name_start..position];
+
+        while position < bytes.len() && bytes[position].is_ascii_whitespace() {
+            position += 1;
+        }
+        if bytes.get(position) != Some(&b'=') {
+            // Attribute without value
+            continue;
+        }
+        position += 1;
+        while position < bytes.len() && bytes[position].is_ascii_whitespace() {
+            position += 1;
+        }
+
+        let value = match bytes.get(position) {
+            Some(&quote @ (b'"' | b'\'')) => {
+                let value_start = position + 1;
+                let value_end = contents[value_start..].find(quote as char).map_or(contents.len(), |end| value_start + end);
+                position = (value_end + 1).min(contents.len());
+                value_start..value_end
+            }
+            _ => {
+                let value_start = position;
+                while position < bytes.len() && !bytes[position].is_ascii_whitespace() && bytes[position] != b'>' {
+                    position += 1;
+                }
+                value_start..position
+            }
+        };
+
+        let attribute_range = attribute_start..position;
+        if URL_ATTRIBUTES.iter().any(|name| name.eq_ignore_ascii_case(attribute_name)) {
+            let trimmed = contents[value.clone()].trim();
+            if !trimmed.is_empty() {
+                let trimmed_start = value.start + (contents[value.clone()].len() - contents[value.clone()].trim_start().len());
+                urls.push(HtmlLink { range: trimmed_start..trimmed_start + trimmed.len(), attribute_range });
+            }
+        } else if SRCSET_ATTRIBUTE.eq_ignore_ascii_case(attribute_name) {
+            urls.extend(
+                find_srcset_urls(contents, value)
+                    .into_iter()
+                    .map(|range| HtmlLink { range, attribute_range: attribute_range.clone() }),
+            );
+        }
+    }
+}
+
+/// Returns the position after the end tag of `element`, starting from `start`.
+fn find_end_tag(contents: &str, start: usize, element: &str) -> usize {
+    let mut position = start;
+    while let Some(found) = contents[position..].find("</") {
+        let name_start = position + found + "</".len();
+        let is_end_tag = contents
+            .get(name_start..name_start + element.len())
+            .is_some_and(|name| name.eq_ignore_ascii_case(element));
+        if is_end_tag {
+            return contents[name_start..].find('>').map_or(contents.len(), |end| name_start + end + 1);
+        }
+        position = name_start;
+    }
+    contents.len()
+}
+
+/// Finds the URLs of a `srcset` attribute, whose value is at `range`.
+/// Candidates are separated by commas, their URL is followed by an optional descriptor.
+fn find_srcset_urls(contents: &str, range: Range<usize>) -> Vec<Range<usize>> {
+    let bytes = contents.as_bytes();
+    let mut urls = vec![];
+
+    let mut position = range.start;
+    while position < range.end {
+        while position < range.end && (bytes[position].is_ascii_whitespace() || bytes[position] == b',') {
+            position += 1;
+        }
+        let url_start = position;
+        while position < range.end && !bytes[position].is_ascii_whitespace() {
+            position += 1;
+        }
+
+        // A URL ending with commas has no descriptor
+        let url = contents[url_start..position].trim_end_matches(',');
+        if !url.is_empty() {
+            urls.push(url_start..url_start + url.len());
+        }
+        if url.len() < position - url_start {
+            continue;
+        }
+
+        // Skip the descriptor, up to the next comma
+        while position < range.end && bytes[position] != b',' {
+            position += 1;
+        }
+    }
+
+    urls
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the text of the URLs found in `contents`.
+    fn urls(contents: &str) -> Vec<&str> {
+        find_link_attribute_values(contents).into_iter().map(|link| &contents[link.range]).collect()
+    }
+
+    #[test]
+    fn finds_quoted_and_unquoted_values() {
+        assert_eq!(urls(r#"<a href="Page%20A.md">A</a><img src='image.png'><a HREF=b.html>B</a>"#), vec!["Page%20A.md", "image.png", "b.html"]);
+        assert_eq!(urls(r#"<a class="x" href = " spaced.html " >"#), vec!["spaced.html"]);
+    }
+
+    #[test]
+    fn finds_the_whole_attribute() {
+        let contents = r#"<a id="x" href="a.html">"#;
+        let link = find_link_attribute_values(contents).remove(0);
+        assert_eq!(&contents[link.attribute_range], r#" href="a.html""#);
+    }
+
+    #[test]
+    fn ignores_other_attributes_and_text() {
+        assert!(urls(r#"<p title="https://www.notion.so/a" hidden>https://www.notion.so/b</p>"#).is_empty());
+        // The value of another attribute can look like a link attribute
+        assert_eq!(urls(r#"<a title='href="no.html"' href="yes.html">"#), vec!["yes.html"]);
+    }
+
+    #[test]
+    fn ignores_comments_scripts_and_styles() {
+        let contents = r#"<!-- <a href="comment.html"> --><script>var a = '<a href="script.html">';</script><style>a { background: url(x.png) }</style><a href="page.html">"#;
+        assert_eq!(urls(contents), vec!["page.html"]);
+    }
+
+    #[test]
+    fn finds_the_urls_of_srcset() {
+        assert_eq!(urls(r#"<img srcset="a.png 1x, b.png 2x,c.png">"#), vec!["a.png", "b.png", "c.png"]);
+        // Like browsers do, a comma is only a separator after a URL
+        assert_eq!(urls(r#"<img srcset="a.png,b.png 480w">"#), vec!["a.png,b.png"]);
+    }
+
+    #[test]
+    fn survives_unclosed_tags() {
+        assert_eq!(urls(r#"<a href="a.html"#), vec!["a.html"]);
+        assert!(urls("<a href=").is_empty());
+        assert!(urls("<script>").is_empty());
+        assert!(urls("a < b").is_empty());
+    }
+
+    #[test]
+    fn finds_the_text_outside_of_tags() {
+        let contents = r#"<p id="a">One</p> <!-- x --><script>two</script>a < b<br/>"#;
+        let texts = find_text_ranges(contents).into_iter().map(|range| &contents[range]).collect::<Vec<_>>();
+        assert_eq!(texts, vec!["One", " ", "a < b"]);
+    }
+}
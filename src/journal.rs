@@ -0,0 +1,492 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use indicatif::ProgressIterator;
+use serde::{Deserialize, Serialize};
+
+use crate::constants::{JOURNAL_DIR_NAME, PROGRESS_BAR_STYLE};
+
+/// Name of the file listing all the operations, inside the journal directory.
+/// It's only written once all operations are staged: its presence means the modifications have begun.
+const PLAN_FILE_NAME: &str = "plan.jsonl";
+/// Directory holding the new contents of the files, until they replace the old contents.
+const STAGED_DIR_NAME: &str = "staged";
+/// Directory holding the old contents of the files, to be able to roll back.
+const ORIGINALS_DIR_NAME: &str = "originals";
+
+/// A single modification of the input directory.
+/// Paths are relative to the root of the input directory,
+/// so that the journal can be used even if the tool is run from another working directory.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Operation {
+    /// Replaces the contents of the file by the staged contents with the same index.
+    Write { path: PathBuf },
+    Rename { old_path: PathBuf, new_path: PathBuf },
+    /// Removes the file, by moving it to the originals with the same index.
+    Remove { path: PathBuf },
+    /// Creates an empty directory, for the renames that place files in it.
+    CreateDir { path: PathBuf },
+    /// Removes the directory if it's empty.
+    RemoveDir { path: PathBuf },
+}
+
+/// The journal makes the in-place modification of the input directory recoverable.
+///
+/// All operations are first staged: new contents and copies of the old contents are stored in the journal directory.
+/// Then the plan of all operations is written, before any modification happens.
+/// Each operation is atomic (a rename), and whether it's done can be checked on the filesystem.
+/// So if the run is interrupted, the next run can either finish it or roll it back.
+/// The journal directory is removed once all operations are applied.
+/// Writes can be staged from several threads at once.
+pub struct Journal {
+    root: PathBuf,
+    dir: PathBuf,
+    operations: Mutex<Vec<Operation>>,
+    /// Has the plan been written? If not, nothing has been modified yet.
+    is_committed: bool,
+}
+
+impl Journal {
+    /// Is there a journal left by an interrupted run in `root`?
+    pub fn exists(root: &Path) -> bool {
+        root.join(JOURNAL_DIR_NAME).exists()
+    }
+
+    /// Creates a new, empty journal in `root`.
+    pub fn create(root: &Path) -> io::Result<Self> {
+        let dir = root.join(JOURNAL_DIR_NAME);
+        fs::create_dir(&dir)?;
+        fs::create_dir(dir.join(STAGED_DIR_NAME))?;
+        fs::create_dir(dir.join(ORIGINALS_DIR_NAME))?;
+
+        Ok(Self {
+            root: root.to_path_buf(),
+            dir,
+            operations: Mutex::new(vec![]),
+            is_committed: false,
+        })
+    }
+
+    /// Opens the journal left by an interrupted run in `root`.
+    pub fn open(root: &Path) -> io::Result<Self> {
+        let dir = root.join(JOURNAL_DIR_NAME);
+        let plan_path = dir.join(PLAN_FILE_NAME);
+
+        let mut operations = vec![];
+        let is_committed = plan_path.exists();
+        if is_committed {
+            for line in BufReader::new(File::open(plan_path)?).lines() {
+                operations.push(serde_json::from_str(&line?)?);
+            }
+        }
+
+        Ok(Self {
+            root: root.to_path_buf(),
+            dir,
+            operations: Mutex::new(operations),
+            is_committed,
+        })
+    }
+
+    /// If the journal is not committed, the interrupted run didn't modify anything.
+    pub fn is_committed(&self) -> bool {
+        self.is_committed
+    }
+
+    pub fn operations_count(&self) -> usize {
+        self.operations.lock().unwrap().len() // Should not panic, no thread panics while holding the lock
+    }
+
+    /// Stages the replacement of the contents of the file at `path` by `new_contents`.
+    /// The writes are applied in the order they are staged, but their order doesn't matter.
+    pub fn stage_write(&self, path: &Path, new_contents: String) -> io::Result<()> {
+        let index = {
+            let mut operations = self.operations.lock().unwrap(); // Should not panic, no thread panics while holding the lock
+            operations.push(Operation::Write {
+                path: self.to_relative(path),
+            });
+            operations.len() - 1
+        };
+
+        // If staging fails, the journal is never applied: the operation without staged contents doesn't matter
+        fs::write(self.staged_path(index), new_contents)?;
+        // A new file has no old contents: rolling back removes it
+        if path.exists() {
+            fs::copy(path, self.original_path(index))?;
+        }
+        Ok(())
+    }
+
+    /// Stages the removal of the file at `path`.
+    pub fn stage_remove(&mut self, path: &Path) {
+        let remove = Operation::Remove {
+            path: self.to_relative(path),
+        };
+        self.get_operations().push(remove);
+    }
+
+    /// Stages the creation of the directory at `path`. Its parent must exist, or be created by a previous operation.
+    pub fn stage_create_dir(&mut self, path: &Path) {
+        let create_dir = Operation::CreateDir {
+            path: self.to_relative(path),
+        };
+        self.get_operations().push(create_dir);
+    }
+
+    /// Stages the removal of the directory at `path`, which is kept if it's not empty.
+    pub fn stage_remove_dir(&mut self, path: &Path) {
+        let remove_dir = Operation::RemoveDir {
+            path: self.to_relative(path),
+        };
+        self.get_operations().push(remove_dir);
+    }
+
+    /// Stages the rename of `old_path` to `new_path`.
+    /// Renames are applied in the order they are staged.
+    pub fn stage_rename(&mut self, old_path: &Path, new_path: &Path) {
+        let rename = Operation::Rename {
+            old_path: self.to_relative(old_path),
+            new_path: self.to_relative(new_path),
+        };
+        self.get_operations().push(rename);
+    }
+
+    /// Writes the plan, then applies all staged operations and removes the journal.
+    pub fn apply(mut self) -> io::Result<()> {
+        self.commit()?;
+        self.resume()
+    }
+
+    /// Writes the plan of all staged operations. From then on, an interrupted run can be resumed or rolled back.
+    fn commit(&mut self) -> io::Result<()> {
+        // The plan is written to a temporary file then renamed,
+        // so that it either exists entirely or not at all.
+        let plan_temp_path = self.dir.join(PLAN_FILE_NAME).with_extension("tmp");
+        let mut plan_file = File::create(&plan_temp_path)?;
+        for operation in self.get_operations().iter() {
+            writeln!(plan_file, "{}", serde_json::to_string(operation)?)?;
+        }
+        plan_file.sync_all()?;
+        fs::rename(plan_temp_path, self.dir.join(PLAN_FILE_NAME))?;
+        self.is_committed = true;
+        Ok(())
+    }
+
+    /// Applies the operations that are not done yet, then removes the journal.
+    pub fn resume(mut self) -> io::Result<()> {
+        let operations = std::mem::take(self.get_operations());
+        for (index, operation) in operations
+            .iter()
+            .enumerate()
+            .progress_with_style(PROGRESS_BAR_STYLE.clone())
+        {
+            match operation {
+                Operation::Write { path } => {
+                    let staged_path = self.staged_path(index);
+                    // The staged file is moved when the operation is done
+                    if staged_path.exists() {
+                        fs::rename(staged_path, self.root.join(path))?;
+                    }
+                }
+                Operation::Rename { old_path, new_path } => {
+                    // Renames are done in order, so the parents of a path not yet renamed are not renamed either
+                    let old_path = self.root.join(old_path);
+                    if old_path.exists() {
+                        rename_without_overwriting(&old_path, &self.root.join(new_path))?;
+                    }
+                }
+                Operation::Remove { path } => {
+                    let path = self.root.join(path);
+                    if path.exists() {
+                        fs::rename(path, self.original_path(index))?;
+                    }
+                }
+                Operation::CreateDir { path } => {
+                    // Without its parent, the directory was created, then its parent renamed
+                    let path = self.root.join(path);
+                    if !path.exists() && path.parent().is_some_and(Path::exists) {
+                        fs::create_dir(path)?;
+                    }
+                }
+                Operation::RemoveDir { path } => {
+                    let path = self.root.join(path);
+                    if path.exists() {
+                        remove_dir_if_empty(&path)?;
+                    }
+                }
+            }
+        }
+
+        self.remove()
+    }
+
+    /// Undoes the operations that are done, in reverse order, then removes the journal.
+    pub fn rollback(mut self) -> io::Result<()> {
+        let operations = std::mem::take(self.get_operations());
+        for (index, operation) in operations
+            .iter()
+            .enumerate()
+            .rev()
+            .progress_with_style(PROGRESS_BAR_STYLE.clone())
+        {
+            match operation {
+                Operation::Write { path } => {
+                    if !self.staged_path(index).exists() {
+                        let original_path = self.original_path(index);
+                        if original_path.exists() {
+                            fs::rename(original_path, self.root.join(path))?;
+                        } else {
+                            fs::remove_file(self.root.join(path))?;
+                        }
+                    }
+                }
+                Operation::Rename { old_path, new_path } => {
+                    // Later renames are already undone, so the renamed path is still in the same parent
+                    let old_path = self.root.join(old_path);
+                    let new_path = self.root.join(new_path);
+                    if new_path.exists() && !old_path.exists() {
+                        fs::rename(new_path, old_path)?;
+                    }
+                }
+                Operation::Remove { path } => {
+                    let original_path = self.original_path(index);
+                    if original_path.exists() {
+                        fs::rename(original_path, self.root.join(path))?;
+                    }
+                }
+                Operation::CreateDir { path } => {
+                    // Later renames are already undone, so the directory is empty again
+                    let path = self.root.join(path);
+                    if path.exists() {
+                        remove_dir_if_empty(&path)?;
+                    }
+                }
+                Operation::RemoveDir { path } => {
+                    let path = self.root.join(path);
+                    if !path.exists() {
+                        fs::create_dir(path)?;
+                    }
+                }
+            }
+        }
+
+        self.remove()
+    }
+
+    /// Removes the journal directory.
+    pub fn remove(self) -> io::Result<()> {
+        fs::remove_dir_all(&self.dir)
+    }
+
+    fn get_operations(&mut self) -> &mut Vec<Operation> {
+        self.operations.get_mut().unwrap() // Should not panic, no thread panics while holding the lock
+    }
+
+    fn staged_path(&self, index: usize) -> PathBuf {
+        self.dir.join(STAGED_DIR_NAME).join(index.to_string())
+    }
+
+    fn original_path(&self, index: usize) -> PathBuf {
+        self.dir.join(ORIGINALS_DIR_NAME).join(index.to_string())
+    }
+
+    fn to_relative(&self, path: &Path) -> PathBuf {
+        path.strip_prefix(&self.root).unwrap().to_path_buf() // Should not panic, all paths are in the root
+    }
+}
+
+/// Renames `from` to `to`, failing instead of overwriting `to` if it already exists.
+/// `fs::rename` would silently replace a file on Unix.
+fn rename_without_overwriting(from: &Path, to: &Path) -> io::Result<()> {
+    if to.symlink_metadata().is_ok() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{:?} already exists", to)));
+    }
+    fs::rename(from, to)
+}
+
+/// Removes the directory at `path`, unless it's not empty: files added by the user are kept.
+fn remove_dir_if_empty(path: &Path) -> io::Result<()> {
+    match fs::remove_dir(path) {
+        Err(e) if e.kind() == io::ErrorKind::DirectoryNotEmpty => Ok(()),
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+    use walkdir::WalkDir;
+
+    use super::*;
+
+    /// Creates an export with a page, its directory and a child page, and a file to remove.
+    fn create_export() -> TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("Page 1")).unwrap();
+        fs::write(dir.path().join("Page 1.md"), "old page").unwrap();
+        fs::write(dir.path().join("Page 1").join("Child 2.md"), "child").unwrap();
+        fs::write(dir.path().join("Old.md"), "old file").unwrap();
+        dir
+    }
+
+    /// Stages the cleaning of the export of `create_export`.
+    fn stage_cleaning(root: &Path) -> Journal {
+        let mut journal = Journal::create(root).unwrap();
+        journal.stage_write(&root.join("Page 1.md"), "new page".to_string()).unwrap();
+        journal.stage_write(&root.join("New.md"), "new file".to_string()).unwrap();
+        journal.stage_rename(&root.join("Page 1").join("Child 2.md"), &root.join("Page 1").join("Child.md"));
+        journal.stage_rename(&root.join("Page 1.md"), &root.join("Page.md"));
+        journal.stage_rename(&root.join("Page 1"), &root.join("Page"));
+        journal.stage_remove(&root.join("Old.md"));
+        journal
+    }
+
+    /// Returns the paths of all entries in `root`, relative to it, with the contents of the files.
+    fn read_tree(root: &Path) -> Vec<(String, Option<String>)> {
+        WalkDir::new(root)
+            .min_depth(1)
+            .sort_by_file_name()
+            .into_iter()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                let path = entry.path().strip_prefix(root).unwrap().to_string_lossy().replace('\\', "/");
+                let contents = entry.file_type().is_file().then(|| fs::read_to_string(entry.path()).unwrap());
+                (path, contents)
+            })
+            .collect()
+    }
+
+    fn cleaned_tree() -> Vec<(String, Option<String>)> {
+        vec![
+            ("New.md".to_string(), Some("new file".to_string())),
+            ("Page".to_string(), None),
+            ("Page/Child.md".to_string(), Some("child".to_string())),
+            ("Page.md".to_string(), Some("new page".to_string())),
+        ]
+    }
+
+    /// Does the first operations of the staged cleaning, like a run interrupted after them.
+    fn interrupt_cleaning(root: &Path) {
+        let mut journal = stage_cleaning(root);
+        journal.commit().unwrap();
+        fs::rename(journal.staged_path(0), root.join("Page 1.md")).unwrap();
+        fs::rename(journal.staged_path(1), root.join("New.md")).unwrap();
+        fs::rename(root.join("Page 1").join("Child 2.md"), root.join("Page 1").join("Child.md")).unwrap();
+    }
+
+    #[test]
+    fn apply_does_all_operations_and_removes_the_journal() {
+        let export = create_export();
+        stage_cleaning(export.path()).apply().unwrap();
+
+        assert!(!Journal::exists(export.path()));
+        assert_eq!(read_tree(export.path()), cleaned_tree());
+    }
+
+    #[test]
+    fn uncommitted_journal_has_not_modified_anything() {
+        let export = create_export();
+        let original_tree = read_tree(export.path());
+        drop(stage_cleaning(export.path()));
+
+        let journal = Journal::open(export.path()).unwrap();
+        assert!(!journal.is_committed());
+        journal.remove().unwrap();
+        assert_eq!(read_tree(export.path()), original_tree);
+    }
+
+    #[test]
+    fn rollback_restores_the_export_after_an_interrupted_run() {
+        let export = create_export();
+        let original_tree = read_tree(export.path());
+        interrupt_cleaning(export.path());
+
+        let journal = Journal::open(export.path()).unwrap();
+        assert!(journal.is_committed());
+        assert_eq!(journal.operations_count(), 6);
+        journal.rollback().unwrap();
+
+        assert!(!Journal::exists(export.path()));
+        assert_eq!(read_tree(export.path()), original_tree);
+    }
+
+    #[test]
+    fn resume_finishes_an_interrupted_run() {
+        let export = create_export();
+        interrupt_cleaning(export.path());
+
+        Journal::open(export.path()).unwrap().resume().unwrap();
+
+        assert!(!Journal::exists(export.path()));
+        assert_eq!(read_tree(export.path()), cleaned_tree());
+    }
+
+    #[test]
+    fn creates_and_removes_the_directories_of_the_renames() {
+        let export = create_export();
+        let root = export.path();
+        let original_tree = read_tree(root);
+
+        let mut journal = Journal::create(root).unwrap();
+        journal.stage_create_dir(&root.join("2024"));
+        journal.stage_create_dir(&root.join("2024").join("01"));
+        journal.stage_rename(&root.join("Page 1.md"), &root.join("2024").join("01").join("Page.md"));
+        journal.apply().unwrap();
+        assert_eq!(fs::read_to_string(root.join("2024").join("01").join("Page.md")).unwrap(), "old page");
+
+        let mut journal = Journal::create(root).unwrap();
+        journal.stage_rename(&root.join("2024").join("01").join("Page.md"), &root.join("Page 1.md"));
+        journal.stage_remove_dir(&root.join("2024").join("01"));
+        journal.stage_remove_dir(&root.join("2024"));
+        journal.apply().unwrap();
+        assert_eq!(read_tree(root), original_tree);
+    }
+
+    #[test]
+    fn rollback_removes_the_created_directories() {
+        let export = create_export();
+        let root = export.path();
+        let original_tree = read_tree(root);
+
+        let mut journal = Journal::create(root).unwrap();
+        journal.stage_create_dir(&root.join("2024"));
+        journal.stage_rename(&root.join("Page 1.md"), &root.join("2024").join("Page.md"));
+        journal.stage_rename(&root.join("Page 1"), &root.join("Page"));
+        journal.commit().unwrap();
+        // Interrupted before the rename of the directory
+        fs::create_dir(root.join("2024")).unwrap();
+        fs::rename(root.join("Page 1.md"), root.join("2024").join("Page.md")).unwrap();
+
+        Journal::open(root).unwrap().rollback().unwrap();
+        assert_eq!(read_tree(root), original_tree);
+    }
+
+    #[test]
+    fn keeps_the_directories_to_remove_that_are_not_empty() {
+        let export = create_export();
+        let root = export.path();
+
+        let mut journal = Journal::create(root).unwrap();
+        journal.stage_remove_dir(&root.join("Page 1"));
+        journal.apply().unwrap();
+        assert_eq!(fs::read_to_string(root.join("Page 1").join("Child 2.md")).unwrap(), "child");
+    }
+
+    #[test]
+    fn apply_never_overwrites_an_existing_file() {
+        let export = create_export();
+        fs::write(export.path().join("Page.md"), "user file").unwrap();
+
+        let error = stage_cleaning(export.path()).apply().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(export.path().join("Page.md")).unwrap(), "user file");
+        // The run can still be rolled back
+        Journal::open(export.path()).unwrap().rollback().unwrap();
+        assert_eq!(fs::read_to_string(export.path().join("Page 1.md")).unwrap(), "old page");
+    }
+}
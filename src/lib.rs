@@ -0,0 +1,30 @@
+pub mod cleaner;
+pub mod content_replacing;
+pub mod error;
+pub mod export_archive;
+pub mod export_source;
+pub mod file_type;
+pub mod journal;
+pub mod link_checking;
+pub mod manifest;
+pub mod naming;
+pub mod notion_object;
+pub mod path_replacing;
+pub mod plan;
+pub mod undo;
+
+mod constants;
+mod csv_cells;
+mod html_links;
+mod markdown_links;
+mod tree_writing;
+mod uriencoding;
+
+pub use cleaner::{CleanReport, Cleaner};
+pub use constants::{JOURNAL_DIR_NAME, UNDO_RECORD_FILE_NAME};
+pub use content_replacing::ExternalLinks;
+pub use error::CleanError;
+pub use file_type::FileType;
+pub use naming::{NameTemplate, NamingOptions, SanitizeNames};
+pub use notion_object::NotionObject;
+pub use path_replacing::resolve_new_names;
@@ -0,0 +1,367 @@
+use std::{
+    collections::HashSet,
+    fs, io,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use aho_corasick::AhoCorasick;
+use indicatif::ParallelProgressIterator;
+use rayon::prelude::*;
+use serde::Serialize;
+use walkdir::WalkDir;
+
+use crate::{
+    constants::{CSV_CELL_LINK_REGEX, NOTION_UUID_REGEX, PROGRESS_BAR_STYLE, UNDO_RECORD_FILE_NAME},
+    content_replacing, csv_cells,
+    error::{CleanError, DiscoveryError, RewriteError},
+    file_type, html_links, markdown_links,
+    notion_object::NotionObjectInfo,
+    path_replacing,
+    undo::UndoRecord,
+    uriencoding,
+};
+
+/// A problem with a link, or with the text of a file, found by `verify_links`.
+#[derive(Debug, Clone, Serialize, thiserror::Error)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum LinkProblem {
+    #[error("Link to a file that does not exist: {link}")]
+    Dangling { link: String },
+    #[error("Link with the UUID {uuid} of a Notion page, which should have been removed: {link}")]
+    RemainingUuid { uuid: String, link: String },
+    #[error("Link to a file outside of the export: {link}")]
+    OutsideRoot { link: String },
+    #[error("Link to Notion instead of a file of the export: {link}")]
+    NotionLink { link: String },
+    #[error("The UUID {uuid} of the page {name}, which should have been removed, remains in the text")]
+    RemainingUuidInText { uuid: String, name: String },
+    #[error("The Notion UUID {uuid}, which should have been removed, remains in the text")]
+    UuidInText { uuid: String },
+}
+
+/// A problem found in a file.
+#[derive(Debug, Clone, Serialize)]
+pub struct LinkWarning {
+    /// Path of the file, relative to the root of the export
+    pub path: PathBuf,
+    /// Line of the problem in the file, starting from 1
+    pub line: usize,
+    #[serde(flatten)]
+    pub problem: LinkProblem,
+}
+
+/// What the verification of the links of an export found.
+/// It's written as JSON, sorted by path and line.
+#[derive(Debug, Default, Serialize)]
+pub struct VerifyReport {
+    /// Number of Markdown, HTML and CSV files whose links were checked
+    pub files_count: usize,
+    /// Number of relative links and Notion links checked
+    pub links_count: usize,
+    pub warnings: Vec<LinkWarning>,
+    /// Errors of the files that could not be checked
+    #[serde(skip)]
+    pub errors: Vec<CleanError>,
+}
+
+impl VerifyReport {
+    /// Did the verification find a problem, or a file it could not check?
+    pub fn has_problems(&self) -> bool {
+        !self.warnings.is_empty() || !self.errors.is_empty()
+    }
+
+    /// Writes the report as JSON to `report_path`.
+    pub fn write(&self, report_path: &Path) -> io::Result<()> {
+        let file = fs::File::create(report_path)?;
+        serde_json::to_writer_pretty(io::BufWriter::new(file), &self)?;
+        Ok(())
+    }
+}
+
+/// Finds the UUIDs of the pages and databases of a cleaned export, left in its files.
+struct UuidsMatcher {
+    /// Finds the UUIDs of all objects
+    uuids_automaton: AhoCorasick,
+    /// The object of each pattern of `uuids_automaton`
+    objects: Vec<NotionObjectInfo>,
+}
+
+/// Checks the links of all Markdown, HTML and CSV files of the export at `root`, as it is on disk.
+/// Each relative link must point to an existing file of the export, and must not have the UUID of a Notion page anymore.
+/// Links to Notion, whose hosts are `notion_hosts`, should have been replaced with relative links.
+/// The UUIDs of its pages must not remain in the text either. If the export was cleaned by this tool, they are found with its undo record.
+/// Otherwise, anything that looks like a Notion UUID is reported, except in the attributes of HTML pages, which have the IDs of their blocks.
+/// Other absolute URLs, and links to a part of the same page, are not checked.
+/// Entries with a component in `ignore` are left out, but links can point to them.
+pub fn verify_links<S: AsRef<str>>(root: &Path, ignore: &HashSet<&str>, notion_hosts: &[S]) -> VerifyReport {
+    let mut report = VerifyReport::default();
+
+    let root = match fs::canonicalize(root) {
+        Ok(root) => root,
+        Err(_) => {
+            report.errors.push(DiscoveryError::InputNotFound(root.to_path_buf()).into());
+            return report;
+        }
+    };
+
+    let notion_hosts = notion_hosts
+        .iter()
+        .map(|host| content_replacing::normalize_notion_host(host.as_ref()))
+        .filter(|host| !host.is_empty())
+        .collect::<Vec<String>>();
+
+    let uuids_matcher = if UndoRecord::exists(&root) {
+        match UndoRecord::read(&root) {
+            Ok(undo_record) => Some(UuidsMatcher::new(undo_record.get_cleaned_objects())),
+            Err(source) => {
+                report.errors.push(CleanError::Io { path: root.join(UNDO_RECORD_FILE_NAME), source });
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let mut paths = vec![];
+    for entry in WalkDir::new(&root).sort_by_file_name() {
+        match entry {
+            Ok(entry) if entry.file_type().is_file() && !file_type::is_ignored(entry.path(), ignore) && has_links(entry.path()) => {
+                paths.push(entry.into_path());
+            }
+            Ok(_) => {}
+            Err(e) => report.errors.push(DiscoveryError::from(e).into()),
+        }
+    }
+    paths.sort();
+
+    let results = paths
+        .par_iter()
+        .progress_with_style(PROGRESS_BAR_STYLE.clone())
+        .map(|path| -> Result<(usize, Vec<LinkWarning>), RewriteError> {
+            let contents = fs::read_to_string(path).map_err(|source| RewriteError::Read { path: path.clone(), source })?;
+            Ok(verify_file(&contents, path, &root, &notion_hosts, uuids_matcher.as_ref()))
+        })
+        .collect::<Vec<_>>();
+
+    for result in results {
+        match result {
+            Ok((links_count, warnings)) => {
+                report.files_count += 1;
+                report.links_count += links_count;
+                report.warnings.extend(warnings);
+            }
+            Err(e) => report.errors.push(e.into()),
+        }
+    }
+
+    report
+}
+
+/// Can the file at `path` have links to check?
+fn has_links(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "md" || ext == "html" || ext == "csv")
+}
+
+impl UuidsMatcher {
+    fn new(objects: Vec<NotionObjectInfo>) -> Self {
+        Self {
+            uuids_automaton: AhoCorasick::new(objects.iter().map(|object| object.uuid.as_str())).unwrap(), // Should not panic, there is no size limit by default
+            objects,
+        }
+    }
+}
+
+/// Checks the links and the text of the file at `path`, in the export at `root`.
+/// Returns the number of links checked, and the problems found.
+fn verify_file(contents: &str, path: &Path, root: &Path, notion_hosts: &[String], uuids_matcher: Option<&UuidsMatcher>) -> (usize, Vec<LinkWarning>) {
+    let mut links_count = 0;
+    let mut problems = vec![];
+
+    let links = find_links(contents, path);
+    for (range, link) in links.iter() {
+        let Some(link_problems) = verify_link(link, path, root, notion_hosts) else {
+            continue;
+        };
+        links_count += 1;
+        problems.extend(link_problems.into_iter().map(|problem| (range.start, problem)));
+    }
+
+    // The UUIDs in links are already reported with their link
+    let is_in_link = |position: usize| links.iter().any(|(range, _)| range.contains(&position));
+    match uuids_matcher {
+        Some(uuids_matcher) => {
+            for m in uuids_matcher.uuids_automaton.find_overlapping_iter(contents) {
+                if is_in_link(m.start()) {
+                    continue;
+                }
+                let object = &uuids_matcher.objects[m.pattern().as_usize()];
+                problems.push((m.start(), LinkProblem::RemainingUuidInText { uuid: object.uuid.clone(), name: object.name.clone() }));
+            }
+        }
+        // Without the record of the cleaning, the pages the UUIDs belonged to are unknown.
+        // The elements of Notion HTML pages have the IDs of their blocks as attributes: only their text is looked at.
+        None => {
+            let text_ranges = path.extension().is_some_and(|ext| ext == "html").then(|| html_links::find_text_ranges(contents));
+            let is_in_text = |position: usize| text_ranges.as_ref().is_none_or(|ranges| ranges.iter().any(|range| range.contains(&position)));
+            for m in NOTION_UUID_REGEX.find_iter(contents) {
+                if is_in_link(m.start()) || !is_in_text(m.start()) {
+                    continue;
+                }
+                problems.push((m.start(), LinkProblem::UuidInText { uuid: m.as_str().to_string() }));
+            }
+        }
+    }
+
+    problems.sort_by_key(|(position, _)| *position);
+    let warnings = problems
+        .into_iter()
+        .map(|(position, problem)| LinkWarning {
+            path: path_replacing::strip_root(path, root),
+            line: contents[..position].matches('\n').count() + 1,
+            problem,
+        })
+        .collect();
+
+    (links_count, warnings)
+}
+
+/// Returns the links of the file at `path`, decoded from HTML and CSV, with where they are in the file.
+/// The links of a CSV file are where their cell is.
+fn find_links(contents: &str, path: &Path) -> Vec<(Range<usize>, String)> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("html") => html_links::find_link_attribute_values(contents)
+            .into_iter()
+            .map(|link| (link.range.clone(), html_escape::decode_html_entities(&contents[link.range]).into_owned()))
+            .collect(),
+        // The links of a cell are the relations to pages, or Markdown links
+        Some("csv") => csv_cells::read_cells(contents)
+            .into_iter()
+            .flat_map(|cell| {
+                let mut links = CSV_CELL_LINK_REGEX
+                    .captures_iter(&cell.value)
+                    .map(|captures| captures["link"].to_string())
+                    .collect::<Vec<String>>();
+                links.extend(find_markdown_links(&cell.value).into_iter().map(|(_, link)| link));
+                links.into_iter().map(move |link| (cell.range.clone(), link))
+            })
+            .collect(),
+        _ => find_markdown_links(contents),
+    }
+}
+
+/// Returns the links of the Markdown `contents`, with their range.
+fn find_markdown_links(contents: &str) -> Vec<(Range<usize>, String)> {
+    markdown_links::find_link_destinations(contents)
+        .into_iter()
+        .map(|destination| {
+            let link = destination.get_link(contents).to_string();
+            (destination.range, link)
+        })
+        .collect()
+}
+
+/// Checks `link`, found in the file at `path`, in the export at `root`.
+/// Returns None if the link is not checked: an absolute URL that is not a link to Notion, or a link to a part of the same page.
+fn verify_link(link: &str, path: &Path, root: &Path, notion_hosts: &[String]) -> Option<Vec<LinkProblem>> {
+    if content_replacing::is_notion_link(link, notion_hosts) {
+        return Some(vec![LinkProblem::NotionLink { link: link.to_string() }]);
+    }
+    if link.is_empty() || link.starts_with(['#', '?']) || content_replacing::is_absolute_url(link) {
+        return None;
+    }
+
+    let link_path = link.split(['?', '#']).next()?;
+    let decoded_path = uriencoding::decode(link_path).unwrap_or(link_path.into());
+    let mut problems = vec![];
+
+    if let Some(uuid) = NOTION_UUID_REGEX.find(&decoded_path) {
+        problems.push(LinkProblem::RemainingUuid { uuid: uuid.as_str().to_string(), link: link.to_string() });
+    }
+
+    let linked_path = path_replacing::normalize_path(&path.parent()?.join(decoded_path.as_ref()));
+    if link.starts_with('/') || !linked_path.starts_with(root) {
+        problems.push(LinkProblem::OutsideRoot { link: link.to_string() });
+    } else if !linked_path.exists() {
+        problems.push(LinkProblem::Dangling { link: link.to_string() });
+    }
+
+    Some(problems)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    /// Creates an export with the page `Home.md`, with `contents`, and the page `Meeting Q1.md`.
+    fn create_export(contents: &str) -> TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("Home.md"), contents).unwrap();
+        fs::write(dir.path().join("Meeting Q1.md"), "Q1").unwrap();
+        dir
+    }
+
+    /// Verifies the export, and returns the number of links checked and the problems found.
+    fn verify(export: &TempDir) -> (usize, Vec<LinkProblem>) {
+        let report = verify_links(export.path(), &HashSet::new(), &["notion.so"]);
+        assert!(report.errors.is_empty());
+        (report.links_count, report.warnings.into_iter().map(|warning| warning.problem).collect())
+    }
+
+    #[test]
+    fn accepts_links_to_existing_files() {
+        let export = create_export("[Q1](Meeting%20Q1.md) [self](Home.md#title) [top](#title) [web](https://example.com) <mailto:a@example.com>");
+        let (links_count, problems) = verify(&export);
+        assert_eq!(links_count, 2);
+        assert!(problems.is_empty(), "{:?}", problems);
+    }
+
+    #[test]
+    fn reports_links_to_missing_files() {
+        // Notion doesn't encode the `:` of page names: these are relative links, not URLs
+        let export = create_export("[Q2](Meeting:%20Q2.md) [gone](Gone.md)");
+        let (links_count, problems) = verify(&export);
+        assert_eq!(links_count, 2);
+        assert!(matches!(problems.as_slice(), [LinkProblem::Dangling { link: q2 }, LinkProblem::Dangling { link: gone }] if q2 == "Meeting:%20Q2.md" && gone == "Gone.md"));
+    }
+
+    #[test]
+    fn reports_uuids_in_links() {
+        let export = create_export("[Q1](Meeting%20Q1%200123456789abcdef0123456789abcdef.md)");
+        let (_, problems) = verify(&export);
+        assert!(matches!(
+            problems.as_slice(),
+            [LinkProblem::RemainingUuid { uuid, .. }, LinkProblem::Dangling { .. }] if uuid == "0123456789abcdef0123456789abcdef"
+        ));
+    }
+
+    #[test]
+    fn reports_links_outside_of_the_export() {
+        let export = create_export("[out](../Outside.md) [abs](/etc/hosts)");
+        let (_, problems) = verify(&export);
+        assert!(matches!(problems.as_slice(), [LinkProblem::OutsideRoot { .. }, LinkProblem::OutsideRoot { .. }]));
+    }
+
+    #[test]
+    fn reports_notion_links() {
+        let export = create_export("[Q1](https://www.notion.so/Meeting-Q1-0123456789abcdef0123456789abcdef)");
+        let (links_count, problems) = verify(&export);
+        assert_eq!(links_count, 1);
+        assert!(matches!(problems.as_slice(), [LinkProblem::NotionLink { .. }]));
+    }
+
+    #[test]
+    fn reports_uuids_in_the_text_but_not_in_html_attributes_without_undo_record() {
+        let export = create_export("");
+        fs::write(
+            export.path().join("Page.html"),
+            r#"<article id="01234567-89ab-cdef-0123-456789abcdef"><p id="11111111-89ab-cdef-0123-456789abcdef">Left 0123456789abcdef0123456789abcdef</p></article>"#,
+        )
+        .unwrap();
+
+        let (_, problems) = verify(&export);
+        assert!(matches!(problems.as_slice(), [LinkProblem::UuidInText { uuid }] if uuid == "0123456789abcdef0123456789abcdef"));
+    }
+}
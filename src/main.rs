@@ -1,137 +1,378 @@
-use file_type::FileType;
-use notion_object::NotionObject;
-
-use clap::Parser;
-use std::{
-    collections::{HashMap, HashSet},
-    path::PathBuf,
-};
-use walkdir::WalkDir;
-
-use crate::file_type::FileMapByName;
-use crate::notion_object::ObjectsMapByName;
-
-mod content_replacing;
-mod constants;
-mod file_type;
-mod notion_object;
-mod path_replacing;
-mod uriencoding;
+use clap::{Parser, Subcommand, ValueEnum};
+use std::{path::PathBuf, process, str::FromStr};
+
+use notion_export_cleaner::cleaner::FileCounts;
+use notion_export_cleaner::error::{RenameError, RenameRefsInFileError};
+use notion_export_cleaner::link_checking::{LinkProblem, VerifyReport};
+use notion_export_cleaner::{CleanError, CleanReport, Cleaner, ExternalLinks, NameTemplate, SanitizeNames};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)] // Read from `Cargo.toml`
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 #[clap(rename_all = "kebab_case")]
 struct NECArgs {
-    /// The directory to walk
+    #[command(subcommand)]
+    command: Option<NECCommand>,
+
+    /// The directory to walk, or the zip file exported by Notion
     #[arg(required = true)]
-    input_dir: String,
+    input: Option<String>,
+
+    /// Write the cleaned export to this directory instead of modifying the input in place. The input is left untouched.
+    /// Required to clean a zip file, in which case it defaults to the path of the zip file, without its extension.
+    #[arg(short, long, value_name = "DIR")]
+    output: Option<String>,
 
     /// List of directory/file names to ignore, separated by commas.
     /// Typically, this can be used to ignore a `.git` directory.
     #[arg(short, long, value_name="FILE_OR_DIR", num_args(1..), value_delimiter = ',')]
     ignore: Vec<String>,
 
+    /// Write the plan of all renames and replacements to this file, as JSON.
+    /// Combine it with `--test` to review the plan without modifying anything.
+    #[arg(long, value_name = "FILE")]
+    plan: Option<String>,
+
+    /// Read the names chosen in a previous run from this JSON file, and write the names chosen in this run to it.
+    /// Use it when cleaning successive exports of the same workspace, so that each page keeps its name from one export to the next.
+    #[arg(long, value_name = "FILE")]
+    manifest: Option<String>,
+
+    /// Hosts of links to Notion pages, in addition to notion.so and notion.site (with their subdomains), separated by commas.
+    /// Typically, the custom domain of a workspace whose pages are published.
+    #[arg(long, value_name = "HOST", num_args(1..), value_delimiter = ',')]
+    notion_hosts: Vec<String>,
+
+    /// What to do with the links to Notion pages that are not part of the export. They are reported as warnings in any case.
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t = ExternalLinksArg::Keep)]
+    external_links: ExternalLinksArg,
+
+    /// What the new names of the pages and databases are made of. Placeholders: {name}, {uuid}, {short_uuid}, {parent},
+    /// and {created} or {created:FORMAT} for the creation time of database rows. For example: "{name} ({short_uuid})".
+    /// A "/" places the pages in subdirectories of the directory they are in, like "{created:%Y}/{name}".
+    #[arg(long, value_name = "TEMPLATE", value_parser = NameTemplate::from_str)]
+    name_template: Option<NameTemplate>,
+
+    /// What to do with the characters of the names of the pages that some file systems don't accept, like `:` or `?` on Windows.
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t = SanitizeNamesArg::Keep)]
+    sanitize_names: SanitizeNamesArg,
+
+    /// Consider that names which only differ by their case or their Unicode normalization are the same, like macOS and Windows do.
+    /// Pages named `Notes` and `notes` in the same directory then get different names, so that the export can be copied anywhere.
+    #[arg(long)]
+    insensitive_collisions: bool,
+
+    /// Maximum length in bytes of the file names of the pages and databases, extension included. Longer names are truncated.
+    #[arg(long, value_name = "BYTES")]
+    max_name_bytes: Option<usize>,
+
+    /// Maximum length in bytes of the paths of the pages and databases, relative to the root of the export. Longer names are truncated.
+    #[arg(long, value_name = "BYTES")]
+    max_path_bytes: Option<usize>,
+
+    /// Once the export is cleaned, check that its relative links point to existing files of the export, without UUID.
+    #[arg(long)]
+    verify: bool,
+
+    /// Write the result of the verification of the links to this file, as JSON. Implies `--verify`.
+    #[arg(long, value_name = "FILE")]
+    verify_report: Option<String>,
+
+    /// Finish the run that was interrupted while modifying the input directory in place.
+    #[arg(long, conflicts_with = "rollback")]
+    resume: bool,
+
+    /// Undo the modifications of the run that was interrupted while modifying the input directory in place.
+    #[arg(long)]
+    rollback: bool,
+
     /// Prevent contents or file name modification. The program will execute everything except the actual writing to file. Use this to test for errors.
     #[arg(short, long)]
     test: bool,
 }
 
+#[derive(Subcommand, Debug)]
+#[clap(rename_all = "kebab_case")]
+enum NECCommand {
+    /// Check the links of an export already cleaned, without modifying it. Typically in CI, to make sure nothing is broken.
+    /// Relative links must point to existing files of the export, without UUID, and links to Notion should not remain.
+    /// If the export was cleaned by this tool, the UUIDs of its pages must not remain in the text either.
+    Verify {
+        /// The directory of the cleaned export
+        input: String,
+
+        /// List of directory/file names to ignore, separated by commas.
+        #[arg(short, long, value_name="FILE_OR_DIR", num_args(1..), value_delimiter = ',')]
+        ignore: Vec<String>,
+
+        /// Hosts of links to Notion pages, in addition to notion.so and notion.site (with their subdomains), separated by commas.
+        #[arg(long, value_name = "HOST", num_args(1..), value_delimiter = ',')]
+        notion_hosts: Vec<String>,
+
+        /// Write the result of the verification to this file, as JSON.
+        #[arg(long, value_name = "FILE")]
+        report: Option<String>,
+    },
+    /// Restore the original names and links of an export cleaned by this tool, in place.
+    /// All the runs on the export are undone, from the last one to the first.
+    Undo {
+        /// The directory of the cleaned export
+        input: String,
+
+        /// Check that the last run can be undone, without modifying anything.
+        #[arg(short, long)]
+        test: bool,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ExternalLinksArg {
+    /// Leave them as they are: they still point to Notion
+    Keep,
+    /// Replace them with their text, or with the title of the page when they have no text
+    PlainText,
+    /// Point them to a placeholder page, created at the root of the export
+    Placeholder,
+}
+
+impl From<ExternalLinksArg> for ExternalLinks {
+    fn from(arg: ExternalLinksArg) -> Self {
+        match arg {
+            ExternalLinksArg::Keep => ExternalLinks::Keep,
+            ExternalLinksArg::PlainText => ExternalLinks::PlainText,
+            ExternalLinksArg::Placeholder => ExternalLinks::Placeholder,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum SanitizeNamesArg {
+    /// Keep the names as they are in Notion
+    Keep,
+    /// Make the names valid on Windows, macOS and Linux
+    Portable,
+}
+
+impl From<SanitizeNamesArg> for SanitizeNames {
+    fn from(arg: SanitizeNamesArg) -> Self {
+        match arg {
+            SanitizeNamesArg::Keep => SanitizeNames::Keep,
+            SanitizeNamesArg::Portable => SanitizeNames::Portable,
+        }
+    }
+}
+
+/// Everything went well. Warnings may have been printed.
+const EXIT_SUCCESS: i32 = 0;
+/// The export was cleaned, but some files were skipped because of errors.
+const EXIT_SKIPPED_FILES: i32 = 1;
+// 2 is the exit code of clap when the arguments are invalid.
+/// The input was left partially modified: the run must be resumed or rolled back.
+const EXIT_INTERRUPTED_RUN: i32 = 3;
+/// Nothing was modified, or the modifications were all undone.
+const EXIT_FAILURE: i32 = 4;
+/// The export was cleaned (or only verified), but the verification found broken links.
+const EXIT_BROKEN_LINKS: i32 = 5;
+
 fn main() {
     let args = NECArgs::parse();
 
+    let result = match args.command {
+        Some(NECCommand::Verify { input, ignore, notion_hosts, report }) => verify(input, ignore, notion_hosts, report),
+        Some(NECCommand::Undo { input, test }) => undo(input, test),
+        None => run(args),
+    };
+
+    let exit_code = match result {
+        Ok(exit_code) => exit_code,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            match e {
+                CleanError::InterruptedRun(_) | CleanError::Rename(RenameError::Apply(_)) => {
+                    eprintln!("Run again with --resume to finish it, or with --rollback to undo it.");
+                    EXIT_INTERRUPTED_RUN
+                }
+                _ => EXIT_FAILURE,
+            }
+        }
+    };
+
+    process::exit(exit_code);
+}
+
+fn run(args: NECArgs) -> Result<i32, CleanError> {
     let is_testing = args.test;
-    let directory = args.input_dir;
-    let directory_path = PathBuf::from(&directory);
 
     if is_testing {
         println!("RUNNING IN TEST MODE");
     }
 
-    println!("Walking directory tree");
-    let ignore: HashSet<&str> = HashSet::from_iter(args.ignore.iter().map(|s| s.as_str()));
-    let file_map: FileMapByName = build_file_map(directory_path, ignore);
-    let files_vec = file_map.values().flatten().collect::<Vec<&FileType>>();
+    let input = args.input.unwrap(); // Should not panic, the input is required without a subcommand
+
+    let mut cleaner = Cleaner::new(&input)
+        .ignore(args.ignore)
+        .plan(args.plan.is_some())
+        .notion_hosts(args.notion_hosts)
+        .external_links(args.external_links.into())
+        .sanitize_names(args.sanitize_names.into())
+        .insensitive_collisions(args.insensitive_collisions)
+        .verify(args.verify || args.verify_report.is_some())
+        .dry_run(is_testing);
+    if let Some(output) = args.output {
+        cleaner = cleaner.output_dir(output);
+    }
+    if let Some(manifest) = args.manifest {
+        cleaner = cleaner.manifest(manifest);
+    }
+    if let Some(name_template) = args.name_template {
+        cleaner = cleaner.name_template(name_template);
+    }
+    if let Some(max_name_bytes) = args.max_name_bytes {
+        cleaner = cleaner.max_name_bytes(max_name_bytes);
+    }
+    if let Some(max_path_bytes) = args.max_path_bytes {
+        cleaner = cleaner.max_path_bytes(max_path_bytes);
+    }
+
+    if args.resume {
+        println!("Resuming the interrupted run");
+        let operations_count = cleaner.resume_interrupted_run()?;
+        println!("{} operations done", operations_count);
+        return Ok(EXIT_SUCCESS);
+    }
+    if args.rollback {
+        println!("Rolling back the interrupted run");
+        let operations_count = cleaner.rollback_interrupted_run()?;
+        println!("{} operations undone", operations_count);
+        return Ok(EXIT_SUCCESS);
+    }
+    if !cleaner.is_zip_input() && cleaner.has_interrupted_run()? {
+        return Err(CleanError::InterruptedRun(PathBuf::from(&input)));
+    }
+
+    match cleaner.get_output_dir() {
+        Some(output_dir) => println!("Cleaning {:?} to {:?}", input, output_dir),
+        None => println!("Cleaning {:?} in place", input),
+    }
+
+    let report = cleaner.run()?;
+    print_report(&report);
 
-    print_file_map_info(&file_map);
+    if let (Some(plan), Some(plan_path)) = (&report.plan, args.plan) {
+        println!("Writing plan to {:?}", plan_path);
+        let plan_path = PathBuf::from(plan_path);
+        plan.write(&plan_path)
+            .map_err(|source| CleanError::Io { path: plan_path, source })?;
+    }
 
-    println!("Building enriched objects from files");
-    let objects = NotionObject::create_objects_from_file_map(&file_map);
-    let mut objects_map: ObjectsMapByName = NotionObject::build_map_by_name(objects);
-    
-    path_replacing::resolve_new_names(&mut objects_map);
+    if let Some(verify_report) = &report.verify {
+        print_verify_report(verify_report);
+        write_verify_report(verify_report, args.verify_report)?;
+    }
 
-    let objects_vec = objects_map
-        .values()
-        .flatten()
-        .collect::<Vec<&NotionObject>>();
+    if !report.errors.is_empty() {
+        Ok(EXIT_SKIPPED_FILES)
+    } else if report.verify.as_ref().is_some_and(VerifyReport::has_problems) {
+        Ok(EXIT_BROKEN_LINKS)
+    } else {
+        Ok(EXIT_SUCCESS)
+    }
+}
 
-    println!("Modifying contents of files");
-    content_replacing::rename_refs_in_all_files(&files_vec, &objects_vec, is_testing);
+fn verify(input: String, ignore: Vec<String>, notion_hosts: Vec<String>, report_path: Option<String>) -> Result<i32, CleanError> {
+    println!("Verifying {:?}", input);
+    let verify_report = Cleaner::new(&input)
+        .ignore(ignore)
+        .notion_hosts(notion_hosts)
+        .verify_links()?;
 
-    println!("Renaming files");
-    path_replacing::rename_objects_files(&objects_vec, is_testing);
+    print_verify_report(&verify_report);
+    write_verify_report(&verify_report, report_path)?;
 
-    println!("Renaming directories");
-    path_replacing::rename_directories(&objects_vec, is_testing);
+    if verify_report.has_problems() {
+        Ok(EXIT_BROKEN_LINKS)
+    } else {
+        Ok(EXIT_SUCCESS)
+    }
 }
 
-/// The file map is a map of file keys to a list of the entries matching this key.
-/// See `FileType::get_file_key` for more information.
-fn build_file_map(
-    directory_path: PathBuf,
-    ignore: HashSet<&str>,
-) -> FileMapByName {
-    let mut file_map: FileMapByName = HashMap::new();
-
-    for entry in WalkDir::new(directory_path) {
-        let entry = entry.unwrap(); // panic if error
-
-        // Ignore hidden directories
-        if entry
-            .path()
-            .components()
-            .map(|c| c.as_os_str().to_str().unwrap())
-            .collect::<HashSet<&str>>()
-            .intersection(&ignore)
-            .count()
-            > 0
-        {
-            continue;
-        }
+fn undo(input: String, is_testing: bool) -> Result<i32, CleanError> {
+    if is_testing {
+        println!("RUNNING IN TEST MODE");
+    }
 
-        let ft = FileType::from(entry.path().to_path_buf());
-        let file_key = ft.get_file_key().to_string();
-        file_map.entry(file_key).or_default().push(ft);
+    println!("Restoring the original export {:?}", input);
+    let undo_report = Cleaner::new(&input).dry_run(is_testing).undo()?;
+    for path in undo_report.modified_files {
+        println!("Warning: {:?} has been modified since it was cleaned, its references can't be restored", path);
     }
 
-    file_map
+    Ok(EXIT_SUCCESS)
 }
 
-fn print_file_map_info(file_map: &FileMapByName) {
-    println!("Found:");
+fn write_verify_report(report: &VerifyReport, report_path: Option<String>) -> Result<(), CleanError> {
+    let Some(report_path) = report_path else {
+        return Ok(());
+    };
 
-    let mut md_files = 0;
-    let mut html_files = 0;
-    let mut csv_files = 0;
-    let mut csv_all_files = 0;
-    let mut directories = 0;
-    let mut other_txt_files = 0;
-    let mut other_bin_files = 0;
-    for ft_vec in file_map.values() {
-        for ft in ft_vec.iter() {
-            match ft {
-                FileType::Markdown(_) => md_files += 1,
-                FileType::Html(_) => html_files += 1,
-                FileType::Csv(_) => csv_files += 1,
-                FileType::CsvAll(_) => csv_all_files += 1,
-                FileType::Dir(_) => directories += 1,
-                FileType::OtherTxt(_) => other_txt_files += 1,
-                FileType::OtherBin(_) => other_bin_files += 1,
-            }
-        }
+    println!("Writing verification report to {:?}", report_path);
+    let report_path = PathBuf::from(report_path);
+    report.write(&report_path)
+        .map_err(|source| CleanError::Io { path: report_path, source })
+}
+
+fn print_report(report: &CleanReport) {
+    for warning in report.warnings.iter() {
+        println!("Warning: non-fatal problem found while renaming references in {:?}:\n\t{}", warning.path, warning.error);
+    }
+
+    for error in report.errors.iter() {
+        eprintln!("Error: left as it is because of a problem:\n\t{}", error);
+    }
+
+    let external_links_count = report
+        .warnings
+        .iter()
+        .filter(|warning| matches!(warning.error, RenameRefsInFileError::ExternalNotionLink { .. }))
+        .count();
+    if external_links_count > 0 {
+        println!("Found {} links to Notion pages that are not part of the export", external_links_count);
+    }
+
+    print_file_counts(&report.file_counts);
+    println!("Renamed {} pages and databases", report.objects_count);
+    if report.cleaned_objects_count > 0 {
+        println!("Left {} pages and databases already renamed by a previous run as they are", report.cleaned_objects_count);
+    }
+    if !report.errors.is_empty() {
+        println!("{} problems left some files as they are, see the errors above", report.errors.len());
     }
+}
+
+fn print_verify_report(report: &VerifyReport) {
+    for warning in report.warnings.iter() {
+        println!("Warning: problem found in {:?}, line {}:\n\t{}", warning.path, warning.line, warning.problem);
+    }
+
+    for error in report.errors.iter() {
+        eprintln!("Error: could not verify the links:\n\t{}", error);
+    }
+
+    let count = |is_kind: fn(&LinkProblem) -> bool| report.warnings.iter().filter(|warning| is_kind(&warning.problem)).count();
+    println!("Verified {} links in {} files:", report.links_count, report.files_count);
+    println!("\t{} links to files that do not exist\n\t{} links with a UUID\n\t{} links outside of the export\n\t{} links to Notion\n\t{} UUIDs in the text",
+        count(|problem| matches!(problem, LinkProblem::Dangling { .. })),
+        count(|problem| matches!(problem, LinkProblem::RemainingUuid { .. })),
+        count(|problem| matches!(problem, LinkProblem::OutsideRoot { .. })),
+        count(|problem| matches!(problem, LinkProblem::NotionLink { .. })),
+        count(|problem| matches!(problem, LinkProblem::RemainingUuidInText { .. } | LinkProblem::UuidInText { .. })),
+    );
+}
+
+fn print_file_counts(counts: &FileCounts) {
+    println!("Found:");
     println!("\t{} markdown files\n\t{} html files\n\t{} csv files\n\t{} csv_all files\n\t{} directories\n\t{} other text files\n\t{} other binary files",
-        md_files, html_files, csv_files, csv_all_files, directories, other_txt_files, other_bin_files
+        counts.markdown, counts.html, counts.csv, counts.csv_all, counts.directories, counts.other_text, counts.other_binary
     );
-    println!("Total: {}", file_map.values().flatten().count());
+    println!("Total: {}", counts.total());
 }
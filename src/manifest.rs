@@ -0,0 +1,139 @@
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::notion_object::NotionObject;
+
+/// The name given to a page or a database in a run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ManifestEntry {
+    /// The name of the object in Notion (without the UUID)
+    name: String,
+    /// The name chosen for the object, possibly with a suffix
+    new_name: String,
+}
+
+/// NameManifest keeps the names chosen for each UUID from one run to the next.
+/// This way, when the same workspace is exported and cleaned again,
+/// each object keeps its previous name (unless it has been renamed in Notion),
+/// and only new objects can get a new suffix.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct NameManifest {
+    /// Sorted by UUID, to keep the file stable between runs
+    entries: BTreeMap<String, ManifestEntry>,
+}
+
+impl NameManifest {
+    /// Reads the manifest of a previous run. Returns an empty manifest if there is no such file.
+    pub fn read(manifest_path: &Path) -> io::Result<Self> {
+        match fs::File::open(manifest_path) {
+            Ok(file) => Ok(serde_json::from_reader(io::BufReader::new(file))?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Returns the name given to this object in a previous run,
+    /// if the object still has the same name in Notion.
+    pub fn get_previous_name(&self, object: &NotionObject) -> Option<&str> {
+        match object {
+            NotionObject::Page(info, ..) | NotionObject::Database(info, ..) => self
+                .entries
+                .get(&info.uuid)
+                .filter(|entry| entry.name == info.name)
+                .map(|entry| entry.new_name.as_str()),
+            NotionObject::Cleaned(..) | NotionObject::OtherText { .. } | NotionObject::OtherBinary { .. } => None,
+        }
+    }
+
+    /// Records the names chosen in this run.
+    /// Entries of objects absent from this run are kept, in case they come back.
+    pub fn update(&mut self, all_objects: &Vec<&NotionObject>) {
+        for object in all_objects {
+            if let NotionObject::Page(info, ..) | NotionObject::Database(info, ..) = object {
+                if let Some(new_name) = info.new_name.as_ref() {
+                    self.entries.insert(
+                        info.uuid.clone(),
+                        ManifestEntry {
+                            name: info.name.clone(),
+                            new_name: new_name.clone(),
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    pub fn write(&self, manifest_path: &Path) -> io::Result<()> {
+        let file = fs::File::create(manifest_path)?;
+        serde_json::to_writer_pretty(io::BufWriter::new(file), self)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::notion_object::NotionObjectInfo;
+
+    use super::*;
+
+    /// Returns the page `name uuid.md`, renamed to `new_name`.
+    fn page(name: &str, uuid: &str, new_name: &str) -> NotionObject {
+        let path = PathBuf::from(format!("export/{} {}.md", name, uuid));
+        let mut page = NotionObject::Page(NotionObjectInfo::new(path, name.to_string(), uuid.to_string(), None), None);
+        page.accept_new_name(new_name.to_string());
+        page
+    }
+
+    #[test]
+    fn reads_an_empty_manifest_when_there_is_no_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = NameManifest::read(&dir.path().join("names.json")).unwrap();
+        assert!(manifest.entries.is_empty());
+    }
+
+    #[test]
+    fn gives_the_previous_name_only_if_the_name_in_notion_is_the_same() {
+        let mut manifest = NameManifest::default();
+        manifest.update(&vec![&page("Notes", "1", "Notes 1")]);
+
+        assert_eq!(manifest.get_previous_name(&page("Notes", "1", "Notes")), Some("Notes 1"));
+        assert_eq!(manifest.get_previous_name(&page("Meeting notes", "1", "Meeting notes")), None);
+        assert_eq!(manifest.get_previous_name(&page("Notes", "2", "Notes")), None);
+    }
+
+    #[test]
+    fn keeps_the_entries_of_the_objects_absent_from_a_run() {
+        let mut manifest = NameManifest::default();
+        manifest.update(&vec![&page("Notes", "1", "Notes 1"), &page("Tasks", "2", "Tasks")]);
+        manifest.update(&vec![&page("Tasks", "2", "Tasks 1")]);
+
+        assert_eq!(manifest.get_previous_name(&page("Notes", "1", "Notes")), Some("Notes 1"));
+        assert_eq!(manifest.get_previous_name(&page("Tasks", "2", "Tasks")), Some("Tasks 1"));
+    }
+
+    #[test]
+    fn writes_the_same_file_whatever_the_order_of_the_objects() {
+        let dir = tempfile::tempdir().unwrap();
+        let (notes, tasks) = (page("Notes", "1", "Notes"), page("Tasks", "2", "Tasks"));
+
+        let mut manifest = NameManifest::default();
+        manifest.update(&vec![&tasks, &notes]);
+        manifest.write(&dir.path().join("first.json")).unwrap();
+
+        let mut manifest = NameManifest::read(&dir.path().join("first.json")).unwrap();
+        manifest.update(&vec![&notes, &tasks]);
+        manifest.write(&dir.path().join("second.json")).unwrap();
+
+        let first = fs::read_to_string(dir.path().join("first.json")).unwrap();
+        assert_eq!(first, fs::read_to_string(dir.path().join("second.json")).unwrap());
+        assert!(first.find("\"1\"").unwrap() < first.find("\"2\"").unwrap());
+    }
+}
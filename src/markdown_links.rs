@@ -0,0 +1,298 @@
+use std::{collections::HashMap, ops::Range};
+
+use pulldown_cmark::{CowStr, Event, LinkType, Options, Parser, Tag, TagEnd};
+
+/// The destination of a link in a Markdown file.
+pub struct LinkDestination {
+    pub range: Range<usize>,
+    /// Is the destination a link on its own, without text: an autolink (its range includes the angle brackets) or a bare URL?
+    pub is_bare: bool,
+    /// For inline links and images, the whole link and its text (or alternative text)
+    pub link_and_text: Option<(Range<usize>, Range<usize>)>,
+}
+
+impl LinkDestination {
+    pub fn new(range: Range<usize>, is_bare: bool) -> Self {
+        Self { range, is_bare, link_and_text: None }
+    }
+
+    /// Returns the link in `contents`, without the angle brackets of an autolink.
+    pub fn get_link<'c>(&self, contents: &'c str) -> &'c str {
+        let link = &contents[self.range.clone()];
+        // The range of an autolink includes its angle brackets
+        match link.strip_prefix('<').and_then(|link| link.strip_suffix('>')) {
+            Some(autolink) if self.is_bare => autolink,
+            _ => link,
+        }
+    }
+}
+
+/// Returns the destinations of all links in the Markdown `contents`:
+/// inline links and images, reference definitions (`[x]: https://...`), autolinks (`<https://...>`), and bare URLs.
+/// Angle brackets around the destination of a link with text are not part of its range.
+/// Code spans and code blocks don't have links.
+/// The destinations are sorted.
+pub fn find_link_destinations(contents: &str) -> Vec<LinkDestination> {
+    let parser = Parser::new_ext(contents, Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH);
+
+    // Reference-style links are rewritten where their destination is: in their definition
+    let mut destinations = parser
+        .reference_definitions()
+        .iter()
+        .filter_map(|(_, definition)| {
+            let span = definition.span.clone();
+            let label_end = span.start + contents[span.clone()].find("]:")?;
+            find_destination(contents, label_end..span.end, &definition.dest)
+        })
+        .map(|range| LinkDestination::new(range, false))
+        .collect::<Vec<LinkDestination>>();
+
+    // Links being parsed, with their start and the end of their text so far. Images can be inside links.
+    let mut open_links: Vec<(LinkType, CowStr, usize, usize)> = vec![];
+    // Text outside of links, where bare URLs can be. Contiguous texts are merged.
+    let mut text_ranges: Vec<Range<usize>> = vec![];
+    // The text of code blocks is not Markdown
+    let mut is_in_code_block = false;
+
+    for (event, range) in parser.into_offset_iter() {
+        match event {
+            Event::End(TagEnd::Link | TagEnd::Image) => {
+                let Some((link_type, dest_url, link_start, text_end)) = open_links.pop() else {
+                    continue;
+                };
+                if let Some((.., parent_text_end)) = open_links.last_mut() {
+                    *parent_text_end = range.end;
+                }
+
+                match link_type {
+                    // [text](destination "title"): the destination is after the text
+                    LinkType::Inline => {
+                        let text_start = link_start + contents[link_start..].find('[').map_or(0, |bracket| bracket + 1);
+                        destinations.extend(find_destination(contents, text_end..range.end, &dest_url).map(|destination_range| LinkDestination {
+                            link_and_text: Some((link_start..range.end, text_start..text_end.max(text_start))),
+                            ..LinkDestination::new(destination_range, false)
+                        }));
+                    }
+                    // <destination>
+                    LinkType::Autolink | LinkType::Email => destinations.push(LinkDestination::new(range, true)),
+                    // Reference-style links are found with their definition, the others are not links to pages
+                    _ => {}
+                }
+            }
+            Event::Start(Tag::Link { link_type, dest_url, .. } | Tag::Image { link_type, dest_url, .. }) => {
+                if let Some((.., parent_text_end)) = open_links.last_mut() {
+                    *parent_text_end = range.end;
+                }
+                open_links.push((link_type, dest_url, range.start, range.start));
+            }
+            Event::Start(Tag::CodeBlock(_)) => is_in_code_block = true,
+            Event::End(TagEnd::CodeBlock) => is_in_code_block = false,
+            _ if !open_links.is_empty() => {
+                if let Some((.., text_end)) = open_links.last_mut() {
+                    *text_end = range.end;
+                }
+            }
+            Event::Text(_) if !is_in_code_block => match text_ranges.last_mut() {
+                Some(last_range) if last_range.end == range.start => last_range.end = range.end,
+                _ => text_ranges.push(range),
+            },
+            _ => {}
+        }
+    }
+
+    for text_range in text_ranges {
+        destinations.extend(find_bare_urls(contents, text_range).into_iter().map(|range| LinkDestination::new(range, true)));
+    }
+
+    destinations.sort_by_key(|destination| destination.range.start);
+    destinations
+}
+
+/// Finds the raw `destination` in `contents`, within `range`.
+/// The destination is not found if it's written with escaped characters, which Notion doesn't do.
+fn find_destination(contents: &str, range: Range<usize>, destination: &str) -> Option<Range<usize>> {
+    if destination.is_empty() {
+        return None;
+    }
+
+    let start = range.start + contents[range].find(destination)?;
+    Some(start..start + destination.len())
+}
+
+/// Finds the URLs starting with `http://` or `https://` in `contents`, within `range`.
+/// Like GitHub does, trailing punctuation and unbalanced closing parentheses are not part of the URL.
+pub fn find_bare_urls(contents: &str, range: Range<usize>) -> Vec<Range<usize>> {
+    let text = &contents[range.clone()];
+    let mut urls = vec![];
+
+    let mut search_start = 0;
+    while let Some(found) = text[search_start..].find("http") {
+        let url_start = search_start + found;
+        let rest = &text[url_start..];
+        if !rest.starts_with("http://") && !rest.starts_with("https://") {
+            search_start = url_start + "http".len();
+            continue;
+        }
+
+        let mut url = rest
+            .find(|c: char| c.is_whitespace() || matches!(c, '<' | '>' | '"' | '`'))
+            .map_or(rest, |url_end| &rest[..url_end]);
+        loop {
+            let trimmed = url.trim_end_matches(['.', ',', ':', ';', '!', '?', '*', '_', '~', '\'']);
+            let trimmed = if trimmed.ends_with(')') && trimmed.matches(')').count() > trimmed.matches('(').count() {
+                &trimmed[..trimmed.len() - 1]
+            } else {
+                trimmed
+            };
+            if trimmed.len() == url.len() {
+                break;
+            }
+            url = trimmed;
+        }
+
+        urls.push(range.start + url_start..range.start + url_start + url.len());
+        search_start = url_start + url.len().max(1);
+    }
+
+    urls
+}
+
+/// Escapes the characters of `text` that would end the text of a link, or be read as Markdown.
+pub fn escape_link_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '[' | ']' | '*' | '_' | '`' | '<' | '>') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Returns the anchors of the headings of the Markdown `contents`, like GitHub makes them.
+/// Headings with the same anchor get a suffix: `title`, `title-1`, `title-2`...
+pub fn find_heading_anchors(contents: &str) -> Vec<String> {
+    let mut anchors = vec![];
+    let mut anchors_count: HashMap<String, usize> = HashMap::new();
+    // Text of the heading being parsed
+    let mut heading_text: Option<String> = None;
+
+    for event in Parser::new_ext(contents, Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH) {
+        match event {
+            Event::Start(Tag::Heading { .. }) => heading_text = Some(String::new()),
+            Event::End(TagEnd::Heading(_)) => {
+                let Some(text) = heading_text.take() else {
+                    continue;
+                };
+                let anchor = get_heading_anchor(&text);
+                let count = anchors_count.entry(anchor.clone()).or_default();
+                anchors.push(match *count {
+                    0 => anchor,
+                    n => format!("{}-{}", anchor, n),
+                });
+                *count += 1;
+            }
+            Event::Text(text) | Event::Code(text) => {
+                if let Some(heading_text) = heading_text.as_mut() {
+                    heading_text.push_str(&text);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    anchors
+}
+
+/// Returns the anchor of a heading with this text, like GitHub makes it:
+/// lowercase, without punctuation, with hyphens instead of spaces.
+pub fn get_heading_anchor(text: &str) -> String {
+    text.trim()
+        .to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            ' ' => Some('-'),
+            '-' | '_' => Some(c),
+            _ if c.is_alphanumeric() => Some(c),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the text of the destinations found in `contents`, and whether they are bare.
+    fn destinations(contents: &str) -> Vec<(&str, bool)> {
+        find_link_destinations(contents)
+            .into_iter()
+            .map(|destination| (&contents[destination.range], destination.is_bare))
+            .collect()
+    }
+
+    #[test]
+    fn finds_inline_links_and_images() {
+        assert_eq!(destinations("[Page](Page%20A.md) and ![alt](image.png)"), vec![("Page%20A.md", false), ("image.png", false)]);
+        assert_eq!(destinations("[Page](https://www.notion.so/x \"title\")"), vec![("https://www.notion.so/x", false)]);
+        // Angle brackets are not part of the destination
+        assert_eq!(destinations("[Page](<Page A.md>)"), vec![("Page A.md", false)]);
+    }
+
+    #[test]
+    fn finds_the_link_and_its_text() {
+        let contents = "See [**Page**](Page.md).";
+        let destination = find_link_destinations(contents).remove(0);
+        let (link_range, text_range) = destination.link_and_text.unwrap();
+        assert_eq!(&contents[link_range], "[**Page**](Page.md)");
+        assert_eq!(&contents[text_range], "**Page**");
+    }
+
+    #[test]
+    fn finds_the_destination_after_the_text() {
+        let contents = "[https://www.notion.so/x](https://www.notion.so/x)";
+        let destination = find_link_destinations(contents).remove(0);
+        assert_eq!(destination.range, 26..contents.len() - 1);
+    }
+
+    #[test]
+    fn finds_reference_definitions_autolinks_and_bare_urls() {
+        assert_eq!(destinations("[Page][p]\n\n[p]: https://www.notion.so/p"), vec![("https://www.notion.so/p", false)]);
+        // The range of an autolink includes its angle brackets
+        assert_eq!(destinations("<https://www.notion.so/a>"), vec![("<https://www.notion.so/a>", true)]);
+        assert_eq!(destinations("See https://www.notion.so/b."), vec![("https://www.notion.so/b", true)]);
+    }
+
+    #[test]
+    fn gives_links_without_the_angle_brackets_of_autolinks() {
+        let contents = "<https://www.notion.so/a> [b](<Page B.md>) https://www.notion.so/c";
+        let links = find_link_destinations(contents).iter().map(|destination| destination.get_link(contents)).collect::<Vec<_>>();
+        assert_eq!(links, vec!["https://www.notion.so/a", "Page B.md", "https://www.notion.so/c"]);
+    }
+
+    #[test]
+    fn ignores_code() {
+        assert!(destinations("`[Page](Page.md)` and `https://www.notion.so/a`").is_empty());
+        assert!(destinations("```\n[Page](Page.md)\nhttps://www.notion.so/a\n```\n").is_empty());
+    }
+
+    #[test]
+    fn sorts_the_destinations() {
+        let contents = "https://a.com [b](b.md) <https://c.com>\n\n[d]: d.md\n\n[x][d]";
+        assert_eq!(destinations(contents), vec![("https://a.com", true), ("b.md", false), ("<https://c.com>", true), ("d.md", false)]);
+    }
+
+    #[test]
+    fn bare_urls_end_like_on_github() {
+        let urls = |text: &str| find_bare_urls(text, 0..text.len()).into_iter().map(|range| text[range].to_string()).collect::<Vec<_>>();
+        assert_eq!(urls("(https://en.wikipedia.org/wiki/Rust_(language))"), vec!["https://en.wikipedia.org/wiki/Rust_(language)"]);
+        assert_eq!(urls("https://a.com/x?y=1, and http://b.com!"), vec!["https://a.com/x?y=1", "http://b.com"]);
+        assert!(urls("httpx://a.com http").is_empty());
+    }
+
+    #[test]
+    fn bare_urls_are_found_within_the_range() {
+        let text = "https://a.com https://b.com";
+        assert_eq!(find_bare_urls(text, 14..text.len()), vec![14..text.len()]);
+    }
+}
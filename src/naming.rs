@@ -0,0 +1,556 @@
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use chrono::NaiveDateTime;
+use rayon::prelude::*;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::{
+    constants::{CREATED_TIME_PROPERTY_NAMES, HTML_CREATED_TIME_REGEX, NOTION_DATE_FORMATS},
+    error::TemplateError,
+    notion_object::NotionObject,
+};
+
+/// Characters that can't be in a file name on Windows, or that macOS shows differently (`:`).
+const UNSAFE_CHARACTERS: [char; 9] = ['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+/// Characters that separate the components of a path, on any platform.
+const PATH_SEPARATORS: [char; 2] = ['/', '\\'];
+/// Separates the directories of a name from its file name, see `NameTemplate`.
+const NAME_SEPARATOR: char = '/';
+/// Replaces the unsafe characters of a name with `SanitizeNames::Portable`.
+const REPLACEMENT_CHARACTER: char = '_';
+/// Format of `{created}` in a name template, when it has none.
+const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+/// Names reserved by Windows, whatever their case and their extension. Windows reads the superscript digits `¹²³` like digits.
+const WINDOWS_RESERVED_NAMES: [&str; 30] = [
+    "CON", "PRN", "AUX", "NUL", "COM0", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9", "COM¹", "COM²", "COM³",
+    "LPT0", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9", "LPT¹", "LPT²", "LPT³",
+];
+
+/// What to do with the characters of the names of the pages that some file systems don't accept.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SanitizeNames {
+    /// Keep the names as they are in Notion
+    #[default]
+    Keep,
+    /// Make names valid on Windows, macOS and Linux: unsafe characters and control characters become `_`,
+    /// trailing dots and spaces are removed, and names reserved by Windows (like `CON` or `NUL`) get a `_`.
+    Portable,
+}
+
+/// How the new names of the pages and databases are chosen, see `path_replacing::resolve_new_names`.
+#[derive(Debug, Clone, Default)]
+pub struct NamingOptions {
+    /// What the names are made of, before being sanitized.
+    pub name_template: NameTemplate,
+    pub sanitize_names: SanitizeNames,
+    /// Compare the names ignoring their case and their Unicode normalization to find the ones that are the same,
+    /// like file systems of macOS (APFS) and Windows (NTFS) do: `Notes` and `notes` can't be in the same directory.
+    pub insensitive_collisions: bool,
+    /// Maximum length of the file names of the pages and databases, in bytes, extension included.
+    pub max_name_bytes: Option<usize>,
+    /// Maximum length of the paths of the pages and databases relative to the root of the export, in bytes.
+    pub max_path_bytes: Option<usize>,
+}
+
+impl NamingOptions {
+    /// Returns `name` as it can be used for a file, following `sanitize_names`.
+    /// The directories of a name, before its `/`, are sanitized one by one.
+    pub fn sanitize(&self, name: &str) -> String {
+        match self.sanitize_names {
+            SanitizeNames::Keep => name.to_string(),
+            SanitizeNames::Portable => name.split(NAME_SEPARATOR).map(sanitize_portable).collect::<Vec<String>>().join("/"),
+        }
+    }
+
+    /// Returns what is compared to know if two paths are the same file, see `insensitive_collisions`.
+    pub fn get_collision_key(&self, path: &Path) -> PathBuf {
+        if self.insensitive_collisions {
+            PathBuf::from(path.to_string_lossy().nfc().collect::<String>().to_lowercase())
+        } else {
+            path.to_path_buf()
+        }
+    }
+
+    /// Returns by how many bytes `path` exceeds `max_name_bytes` or `max_path_bytes`,
+    /// in the directory whose path relative to the root of the export is `parent`.
+    /// `path` is a file name, or a file name in subdirectories of `parent`: only the file name is measured for `max_name_bytes`.
+    pub fn get_excess_bytes(&self, parent: &Path, path: &Path) -> usize {
+        let file_name_len = path.file_name().map_or(0, |file_name| file_name.len());
+        let path_len = if parent.as_os_str().is_empty() {
+            path.as_os_str().len()
+        } else {
+            parent.as_os_str().len() + 1 + path.as_os_str().len()
+        };
+
+        let name_excess = self.max_name_bytes.map_or(0, |max| file_name_len.saturating_sub(max));
+        let path_excess = self.max_path_bytes.map_or(0, |max| path_len.saturating_sub(max));
+        name_excess.max(path_excess)
+    }
+}
+
+/// Returns `name` shortened by at least `excess_bytes`, cut at a UTF-8 boundary.
+/// Trailing spaces and dots are removed, but the first character is always kept, even if it's not enough.
+/// Only the file name of a name with directories is shortened: `2024/Meeting` gives `2024/Mee`.
+pub fn truncate(name: &str, excess_bytes: usize) -> String {
+    if let Some((directories, file_name)) = name.rsplit_once(NAME_SEPARATOR) {
+        return format!("{}/{}", directories, truncate(file_name, excess_bytes));
+    }
+
+    let mut end = name.len().saturating_sub(excess_bytes);
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    let truncated = name[..end].trim_end_matches(['.', ' ']);
+    if truncated.is_empty() {
+        name.chars().take(1).collect()
+    } else {
+        truncated.to_string()
+    }
+}
+
+/// Makes `name` valid on all file systems, see `SanitizeNames::Portable`.
+fn sanitize_portable(name: &str) -> String {
+    let mut sanitized = name
+        .chars()
+        .map(|c| if UNSAFE_CHARACTERS.contains(&c) || c.is_control() { REPLACEMENT_CHARACTER } else { c })
+        .collect::<String>()
+        .trim_end_matches(['.', ' '])
+        .to_string();
+
+    // `CON.txt` is as reserved as `CON`
+    let stem = sanitized.split('.').next().unwrap_or_default().trim_end();
+    if sanitized.is_empty() || WINDOWS_RESERVED_NAMES.iter().any(|reserved| reserved.eq_ignore_ascii_case(stem)) {
+        sanitized.push(REPLACEMENT_CHARACTER);
+    }
+
+    sanitized
+}
+
+/// What the new name of a page or a database is made of, like `{name} ({short_uuid})`.
+/// Placeholders are between braces, and `{{` and `}}` are literal braces:
+/// - `{name}`: the name of the page in Notion,
+/// - `{uuid}` and `{short_uuid}`: its Notion ID, and the first 8 characters of it,
+/// - `{parent}`: the new name of the page or database it's in, empty at the root of the export,
+/// - `{created}` or `{created:FORMAT}`: the creation time of a database row, formatted with the `strftime` FORMAT (`%Y-%m-%d` by default),
+///   empty if the page has no "Created time" property.
+///
+/// The result is a file name, without leading and trailing spaces, unless the template has a `/`:
+/// then the page is placed in subdirectories of the directory it is in, like `{created:%Y}/{name}` for database rows by year,
+/// or `{parent}/{name}` for pages in a directory named after their parent. The template can't contain `\`.
+/// Empty directories are left out, and `.` and `..` can't be directories.
+/// The text next to an empty placeholder at the start or the end is left out: `{parent} - {name}` gives `name` at the root,
+/// and so does `{parent}/{name}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameTemplate {
+    parts: Vec<TemplatePart>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TemplatePart {
+    Text(String),
+    Name,
+    Uuid,
+    ShortUuid,
+    Parent,
+    Created { format: String },
+}
+
+/// What the placeholders of a `NameTemplate` are replaced with, for one object.
+pub struct TemplateValues<'a> {
+    pub name: &'a str,
+    pub uuid: &'a str,
+    pub parent: &'a str,
+    pub created: Option<NaiveDateTime>,
+}
+
+impl NameTemplate {
+    /// Returns the name given by the template with `values`.
+    /// The text between an empty placeholder and the start or the end of the name is left out,
+    /// like ` - ` in `{parent} - {name}` at the root of the export.
+    /// It's the name of the page if the template gives an empty name.
+    /// The directories and the file name, separated by `/`, are trimmed, and the empty ones are left out.
+    /// Fails if a creation time can't be formatted, see `format_created`, or if a directory is `.` or `..`.
+    pub fn render(&self, values: &TemplateValues) -> Result<String, TemplateError> {
+        let rendered_parts = self
+            .parts
+            .iter()
+            .map(|part| {
+                let rendered = match part {
+                    TemplatePart::Text(text) => text.clone(),
+                    TemplatePart::Name => values.name.to_string(),
+                    TemplatePart::Uuid => values.uuid.to_string(),
+                    TemplatePart::ShortUuid => values.uuid.chars().take(8).collect(),
+                    TemplatePart::Parent => values.parent.to_string(),
+                    TemplatePart::Created { format } => match values.created {
+                        Some(created) => format_created(created, format)?,
+                        None => String::new(),
+                    },
+                };
+                Ok((matches!(part, TemplatePart::Text(_)), rendered))
+            })
+            .collect::<Result<Vec<(bool, String)>, TemplateError>>()?;
+
+        let is_empty_placeholder = |(is_text, rendered): &(bool, String)| !is_text && rendered.is_empty();
+        let is_filled_placeholder = |(is_text, rendered): &(bool, String)| !is_text && !rendered.is_empty();
+        let first_filled = rendered_parts.iter().position(is_filled_placeholder).unwrap_or(rendered_parts.len());
+        let last_filled = rendered_parts.iter().rposition(is_filled_placeholder).map_or(0, |i| i + 1);
+        let start = if rendered_parts[..first_filled].iter().any(is_empty_placeholder) { first_filled } else { 0 };
+        let end = if rendered_parts[last_filled..].iter().any(is_empty_placeholder) { last_filled } else { rendered_parts.len() };
+
+        let rendered = if start < end {
+            rendered_parts[start..end].iter().map(|(_, rendered)| rendered.as_str()).collect::<String>()
+        } else {
+            String::new()
+        };
+
+        let components = rendered.split(NAME_SEPARATOR).map(str::trim).filter(|component| !component.is_empty()).collect::<Vec<&str>>();
+        if let Some(component) = components.iter().find(|component| matches!(**component, "." | "..")) {
+            return Err(TemplateError::RelativeDirectory(component.to_string()));
+        }
+
+        if components.is_empty() {
+            Ok(values.name.to_string())
+        } else {
+            Ok(components.join("/"))
+        }
+    }
+
+    /// Does the template need the creation times of the pages?
+    pub fn uses_created(&self) -> bool {
+        self.parts.iter().any(|part| matches!(part, TemplatePart::Created { .. }))
+    }
+}
+
+impl Default for NameTemplate {
+    /// `{name}`: the name of the page, as it is.
+    fn default() -> Self {
+        Self { parts: vec![TemplatePart::Name] }
+    }
+}
+
+impl FromStr for NameTemplate {
+    type Err = TemplateError;
+
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        let mut parts = vec![];
+        let mut text = String::new();
+        let mut rest = template;
+
+        while let Some(brace_index) = rest.find(['{', '}']) {
+            text.push_str(&rest[..brace_index]);
+            let brace = &rest[brace_index..brace_index + 1];
+            rest = &rest[brace_index + 1..];
+
+            // A doubled brace is a literal one
+            if let Some(after) = rest.strip_prefix(brace) {
+                text.push_str(brace);
+                rest = after;
+                continue;
+            }
+            if brace == "}" {
+                return Err(TemplateError::Unopened);
+            }
+
+            let end = rest.find('}').ok_or(TemplateError::Unclosed)?;
+            if !text.is_empty() {
+                parts.push(TemplatePart::Text(std::mem::take(&mut text)));
+            }
+            parts.push(parse_placeholder(&rest[..end])?);
+            rest = &rest[end + 1..];
+        }
+        text.push_str(rest);
+        if !text.is_empty() {
+            parts.push(TemplatePart::Text(text));
+        }
+
+        for part in &parts {
+            if let TemplatePart::Text(text) = part {
+                // `/` places the pages in subdirectories, `\\` would only do it on Windows
+                if let Some(separator) = text.chars().find(|c| PATH_SEPARATORS.contains(c) && *c != NAME_SEPARATOR) {
+                    return Err(TemplateError::PathSeparator(separator));
+                }
+            }
+        }
+
+        Ok(Self { parts })
+    }
+}
+
+/// Parses what is between the braces of a placeholder.
+fn parse_placeholder(placeholder: &str) -> Result<TemplatePart, TemplateError> {
+    match placeholder.split_once(':') {
+        Some(("created", format)) => {
+            // Any date will do: whether a format can be used doesn't depend on the date
+            format_created(NaiveDateTime::default(), format)?;
+            Ok(TemplatePart::Created { format: format.to_string() })
+        }
+        Some(_) => Err(TemplateError::UnknownPlaceholder(placeholder.to_string())),
+        None => match placeholder {
+            "name" => Ok(TemplatePart::Name),
+            "uuid" => Ok(TemplatePart::Uuid),
+            "short_uuid" => Ok(TemplatePart::ShortUuid),
+            "parent" => Ok(TemplatePart::Parent),
+            "created" => Ok(TemplatePart::Created { format: DEFAULT_DATE_FORMAT.to_string() }),
+            _ => Err(TemplateError::UnknownPlaceholder(placeholder.to_string())),
+        },
+    }
+}
+
+/// Returns `created` formatted with the `strftime` `format` of a `{created:FORMAT}` placeholder.
+/// Fails if the format is invalid, or needs what a creation time doesn't have, like the time zone of `%z`,
+/// and if the result can't be in a file name, like the `01/05/24` of `%D`.
+fn format_created(created: NaiveDateTime, format: &str) -> Result<String, TemplateError> {
+    let mut formatted = String::new();
+    write!(formatted, "{}", created.format(format)).map_err(|_| TemplateError::InvalidDateFormat(format.to_string()))?;
+
+    if let Some(separator) = formatted.chars().find(|c| PATH_SEPARATORS.contains(c)) {
+        return Err(TemplateError::DateWithPathSeparator { format: format.to_string(), separator });
+    }
+    Ok(formatted)
+}
+
+/// Finds the creation times of the pages read with `read`, by path, for the `{created}` placeholder of `NameTemplate`.
+/// The pages are read on all cores.
+/// Only database rows have one, in their "Created time" property. Pages that can't be read have none:
+/// their errors are reported when their references are renamed.
+pub fn find_creation_times(all_objects: &[NotionObject], read: impl Fn(&Path) -> io::Result<String> + Sync) -> HashMap<PathBuf, NaiveDateTime> {
+    all_objects
+        .par_iter()
+        .filter(|object| matches!(object, NotionObject::Page(..)))
+        .filter_map(|object| {
+            let path = object.get_path();
+            let contents = read(path).ok()?;
+            let created = if path.extension().is_some_and(|ext| ext == "html") {
+                HTML_CREATED_TIME_REGEX.captures(&contents).map(|captures| captures["date"].to_string())
+            } else {
+                find_markdown_created_time(&contents)
+            }?;
+
+            let created = NOTION_DATE_FORMATS
+                .iter()
+                .find_map(|format| NaiveDateTime::parse_from_str(created.trim(), format).ok())?;
+            Some((path.clone(), created))
+        })
+        .collect()
+}
+
+/// Finds the "Created time" property in the properties listed under the title of a Markdown page, as `Created: January 5, 2024 3:04 PM`.
+fn find_markdown_created_time(contents: &str) -> Option<String> {
+    contents
+        .lines()
+        .skip_while(|line| line.starts_with("# ") || line.trim().is_empty())
+        .take_while(|line| line.contains(": "))
+        .filter_map(|line| line.split_once(": "))
+        .find(|(property, _)| CREATED_TIME_PROPERTY_NAMES.iter().any(|name| name.eq_ignore_ascii_case(property.trim())))
+        .map(|(_, value)| value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn values(created: Option<NaiveDateTime>) -> TemplateValues<'static> {
+        TemplateValues { name: "Write spec", uuid: "0123456789abcdef0123456789abcdef", parent: "Tasks", created }
+    }
+
+    fn created() -> Option<NaiveDateTime> {
+        NaiveDate::from_ymd_opt(2024, 1, 5).and_then(|date| date.and_hms_opt(15, 4, 0))
+    }
+
+    fn render(template: &str, values: &TemplateValues) -> String {
+        NameTemplate::from_str(template).unwrap().render(values).unwrap()
+    }
+
+    #[test]
+    fn parse_rejects_invalid_templates() {
+        assert!(matches!(NameTemplate::from_str("{title}"), Err(TemplateError::UnknownPlaceholder(p)) if p == "title"));
+        assert!(matches!(NameTemplate::from_str("{name:%Y}"), Err(TemplateError::UnknownPlaceholder(_))));
+        assert!(matches!(NameTemplate::from_str("{name"), Err(TemplateError::Unclosed)));
+        assert!(matches!(NameTemplate::from_str("name}"), Err(TemplateError::Unopened)));
+        assert!(matches!(NameTemplate::from_str("{parent}\\{name}"), Err(TemplateError::PathSeparator('\\'))));
+    }
+
+    #[test]
+    fn parse_rejects_date_formats_that_fail_or_give_separators() {
+        assert!(matches!(NameTemplate::from_str("{created:%Q}"), Err(TemplateError::InvalidDateFormat(_))));
+        // A creation time has no time zone
+        assert!(matches!(NameTemplate::from_str("{created:%z} {name}"), Err(TemplateError::InvalidDateFormat(_))));
+        assert!(matches!(NameTemplate::from_str("{created:%D} {name}"), Err(TemplateError::DateWithPathSeparator { separator: '/', .. })));
+        assert!(NameTemplate::from_str("{created:%Y-%m-%d %Hh%M} {name}").is_ok());
+    }
+
+    #[test]
+    fn parse_keeps_doubled_braces_as_text() {
+        assert_eq!(render("{{{name}}}", &values(None)), "{Write spec}");
+        assert_eq!(render("}}{{", &values(None)), "}{");
+    }
+
+    #[test]
+    fn render_replaces_placeholders() {
+        assert_eq!(render("{name}", &values(None)), "Write spec");
+        assert_eq!(render("{name} ({short_uuid})", &values(None)), "Write spec (01234567)");
+        assert_eq!(render("{uuid}", &values(None)), "0123456789abcdef0123456789abcdef");
+        assert_eq!(render("{parent} - {name}", &values(None)), "Tasks - Write spec");
+        assert_eq!(render("{created} {name}", &values(created())), "2024-01-05 Write spec");
+        assert_eq!(render("{created:%Y%m%d-%H%M} {name}", &values(created())), "20240105-1504 Write spec");
+    }
+
+    #[test]
+    fn render_leaves_out_the_text_next_to_empty_placeholders_at_the_ends() {
+        let at_root = TemplateValues { parent: "", ..values(None) };
+        assert_eq!(render("{parent} - {name}", &at_root), "Write spec");
+        assert_eq!(render("{name} ({created})", &values(None)), "Write spec");
+        assert_eq!(render("[{created}] {name} - {parent}", &at_root), "Write spec");
+        // Only the text at the ends is left out
+        assert_eq!(render("{name} - {parent} - {name}", &at_root), "Write spec -  - Write spec");
+    }
+
+    #[test]
+    fn render_places_pages_in_subdirectories_with_a_slash() {
+        let at_root = TemplateValues { parent: "", ..values(None) };
+        assert_eq!(render("{parent}/{name}", &values(None)), "Tasks/Write spec");
+        assert_eq!(render("{parent}/{name}", &at_root), "Write spec");
+        assert_eq!(render("{created:%Y}/{created:%m}/{name}", &values(created())), "2024/01/Write spec");
+        assert_eq!(render("{created:%Y}/{name}", &values(None)), "Write spec");
+        // Directories are trimmed, and empty ones left out
+        assert_eq!(render(" {parent} / {created} / {name} ", &values(None)), "Tasks/Write spec");
+        assert_eq!(render("{name}/", &values(None)), "Write spec");
+
+        let template = NameTemplate::from_str("../{name}").unwrap();
+        assert!(matches!(template.render(&values(None)), Err(TemplateError::RelativeDirectory(d)) if d == ".."));
+    }
+
+    #[test]
+    fn render_gives_the_name_when_the_template_gives_nothing() {
+        let at_root = TemplateValues { parent: "", ..values(None) };
+        assert_eq!(render("{parent}", &at_root), "Write spec");
+        assert_eq!(render(" ", &at_root), "Write spec");
+    }
+
+    #[test]
+    fn render_fails_on_date_formats_that_fail_or_give_separators() {
+        let template = NameTemplate { parts: vec![TemplatePart::Created { format: "%z".to_string() }] };
+        assert!(matches!(template.render(&values(created())), Err(TemplateError::InvalidDateFormat(_))));
+        // Without a creation time, there is nothing to format
+        assert_eq!(template.render(&values(None)).unwrap(), "Write spec");
+
+        let template = NameTemplate { parts: vec![TemplatePart::Created { format: "%x".to_string() }] };
+        assert!(matches!(template.render(&values(created())), Err(TemplateError::DateWithPathSeparator { separator: '/', .. })));
+    }
+
+    #[test]
+    fn truncate_cuts_at_utf8_boundaries() {
+        assert_eq!(truncate("abc", 0), "abc");
+        assert_eq!(truncate("abc", 1), "ab");
+        // `é` is 2 bytes: cutting 1 byte of it removes all of it
+        assert_eq!(truncate("été", 1), "ét");
+        assert_eq!(truncate("été", 2), "ét");
+        assert_eq!(truncate("été", 3), "é");
+        assert_eq!(truncate("a😀", 1), "a");
+        assert_eq!(truncate("a😀", 4), "a");
+    }
+
+    #[test]
+    fn truncate_removes_trailing_spaces_and_dots_but_keeps_the_first_character() {
+        assert_eq!(truncate("ab. cd", 2), "ab");
+        assert_eq!(truncate("a...", 1), "a");
+        assert_eq!(truncate("éé", 4), "é");
+        assert_eq!(truncate("😀", 10), "😀");
+        assert_eq!(truncate("", 1), "");
+    }
+
+    #[test]
+    fn truncate_only_cuts_the_file_name() {
+        assert_eq!(truncate("2024/Meeting", 4), "2024/Mee");
+        assert_eq!(truncate("2024/Meeting", 20), "2024/M");
+    }
+
+    #[test]
+    fn sanitize_keeps_names_as_they_are_by_default() {
+        assert_eq!(NamingOptions::default().sanitize("Q1: plan/review?"), "Q1: plan/review?");
+    }
+
+    #[test]
+    fn sanitize_portable_sanitizes_each_directory() {
+        let options = NamingOptions { sanitize_names: SanitizeNames::Portable, ..Default::default() };
+        assert_eq!(options.sanitize("Q1: plan/CON/review?"), "Q1_ plan/CON_/review_");
+    }
+
+    #[test]
+    fn excess_bytes_measure_the_file_name_and_the_whole_path() {
+        let options = NamingOptions { max_name_bytes: Some(10), max_path_bytes: Some(30), ..Default::default() };
+        assert_eq!(options.get_excess_bytes(Path::new("Tasks"), Path::new("2024/Meeting.md")), 0);
+        assert_eq!(options.get_excess_bytes(Path::new("Tasks"), Path::new("2024/Meetings.md")), 1);
+        assert_eq!(options.get_excess_bytes(Path::new("Projects/Roadmap"), Path::new("2024/Meeting.md")), 2);
+    }
+
+    #[test]
+    fn sanitize_portable_replaces_unsafe_and_control_characters() {
+        assert_eq!(sanitize_portable("Q1: plan/review?"), "Q1_ plan_review_");
+        assert_eq!(sanitize_portable(r#"<a> "b" \c| *"#), "_a_ _b_ _c_ _");
+        assert_eq!(sanitize_portable("tab\there"), "tab_here");
+        assert_eq!(sanitize_portable("Été 2024"), "Été 2024");
+    }
+
+    #[test]
+    fn sanitize_portable_removes_trailing_dots_and_spaces() {
+        assert_eq!(sanitize_portable("To do... "), "To do");
+        assert_eq!(sanitize_portable("v1.2"), "v1.2");
+        assert_eq!(sanitize_portable(". ."), "_");
+    }
+
+    #[test]
+    fn sanitize_portable_escapes_names_reserved_by_windows() {
+        assert_eq!(sanitize_portable("CON"), "CON_");
+        assert_eq!(sanitize_portable("nul.txt"), "nul.txt_");
+        assert_eq!(sanitize_portable("Com1 .md"), "Com1 .md_");
+        assert_eq!(sanitize_portable("CONSOLE"), "CONSOLE");
+        assert_eq!(sanitize_portable("LPT10"), "LPT10");
+        assert_eq!(sanitize_portable("COM0"), "COM0_");
+        assert_eq!(sanitize_portable("lpt0.md"), "lpt0.md_");
+        assert_eq!(sanitize_portable("COM\u{b9}"), "COM\u{b9}_");
+        assert_eq!(sanitize_portable("com\u{b2}.csv"), "com\u{b2}.csv_");
+        assert_eq!(sanitize_portable("LPT\u{b3}"), "LPT\u{b3}_");
+        assert_eq!(sanitize_portable("COM\u{2074}"), "COM\u{2074}");
+    }
+
+    #[test]
+    fn collision_keys_ignore_case_and_normalization_only_if_asked() {
+        let (composed, decomposed) = (Path::new("export/Caf\u{e9}.md"), Path::new("export/CAFE\u{301}.md"));
+        assert_ne!(NamingOptions::default().get_collision_key(composed), NamingOptions::default().get_collision_key(decomposed));
+
+        let options = NamingOptions { insensitive_collisions: true, ..Default::default() };
+        assert_eq!(options.get_collision_key(composed), options.get_collision_key(decomposed));
+        assert_ne!(options.get_collision_key(composed), options.get_collision_key(Path::new("export/Cafe.md")));
+    }
+
+    #[test]
+    fn finds_the_creation_times_of_database_rows() {
+        use crate::notion_object::NotionObjectInfo;
+
+        let page = |file_name: &str| {
+            NotionObject::Page(NotionObjectInfo::new(PathBuf::from(file_name), "Row".to_string(), "1".to_string(), None), None)
+        };
+        let objects = [page("Row 1.md"), page("Row 2.html"), page("Page 3.md"), page("Missing 4.md")];
+        let contents = HashMap::from([
+            ("Row 1.md", "# Row\n\nStatus: Done\nCreated: January 5, 2024 3:04 PM\n\nText"),
+            ("Row 2.html", r#"<tr class="property-row property-row-created_time"><th>Created</th><td><time>@January 5, 2024 15:04</time></td></tr>"#),
+            ("Page 3.md", "# Page\n\nCreated: the text of the page"),
+        ]);
+
+        let creation_times = find_creation_times(&objects, |path| {
+            contents.get(path.to_str().unwrap()).map(|c| c.to_string()).ok_or(io::ErrorKind::NotFound.into())
+        });
+        let expected = HashMap::from([(PathBuf::from("Row 1.md"), created().unwrap()), (PathBuf::from("Row 2.html"), created().unwrap())]);
+        assert_eq!(creation_times, expected);
+    }
+}
@@ -1,6 +1,6 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::{collections::HashMap, ffi::OsString, path::{Path, PathBuf}};
 
-use crate::{file_type::{FileMapByName, FileType}, uriencoding};
+use crate::{constants::NAME_UUID_REGEX, error::PlanningError, file_type::{FileMapByName, FileType}, uriencoding};
 
 /// The `index.html` file has a `index` key.
 /// It shouldn't be totally ignored because it has content to be modified,
@@ -65,12 +65,12 @@ impl ReplacableReferencesContainer {
         // Some of the references are html-encoded
         // Others, a mix of both
         
-        let old_name_html_encoded = html_escape::encode_safe(&old_name);
-        let new_name_html_encoded = html_escape::encode_safe(&new_name);
+        let old_name_html_encoded = html_escape::encode_safe(old_name);
+        let new_name_html_encoded = html_escape::encode_safe(new_name);
 
         Self { global_references: [
-                    ReplacableReferences{ old_ref: uriencoding::encode(&old_name).into_owned(), new_ref: uriencoding::encode(&new_name).into_owned() },
-                    ReplacableReferences{ old_ref: html_escape::encode_safe(&old_name).into_owned(), new_ref: html_escape::encode_safe(&new_name).into_owned() },
+                    ReplacableReferences{ old_ref: uriencoding::encode(old_name).into_owned(), new_ref: uriencoding::encode(new_name).into_owned() },
+                    ReplacableReferences{ old_ref: html_escape::encode_safe(old_name).into_owned(), new_ref: html_escape::encode_safe(new_name).into_owned() },
                     ReplacableReferences{ old_ref: uriencoding::encode(&old_name_html_encoded).into_owned(), new_ref: uriencoding::encode(&new_name_html_encoded).into_owned() },
                     ReplacableReferences{ old_ref: old_name.to_owned(), new_ref: new_name.to_owned() },
                 ]
@@ -85,6 +85,9 @@ impl ReplacableReferencesContainer {
 pub enum NotionObject {
     Page(NotionObjectInfo, Option<ReplacableReferencesContainer>),
     Database(NotionObjectInfo, NotionDatabaseInfo, Option<ReplacableReferencesContainer>),
+    /// A page or a database renamed by a previous run: its path is already the one with its new name.
+    /// It's not renamed again, but the references to its old name still are.
+    Cleaned(NotionObjectInfo, Option<ReplacableReferencesContainer>),
     OtherText { path: PathBuf },
     OtherBinary { path: PathBuf },
 }
@@ -92,8 +95,10 @@ pub enum NotionObject {
 // FACTORY
 impl NotionObject {
     /// Returns a list of all NotionObjects.
-    pub fn create_objects_from_file_map(all_files: &FileMapByName) -> Vec<NotionObject> {
+    /// The files of a key that can't be made into objects are left out, and their errors returned.
+    pub fn create_objects_from_file_map(all_files: &FileMapByName) -> (Vec<NotionObject>, Vec<PlanningError>) {
         let mut notion_objects = Vec::new();
+        let mut errors = Vec::new();
 
         for (key, file_types) in all_files.iter() {
             let mut md_path = None;
@@ -133,21 +138,39 @@ impl NotionObject {
                 }
             }
 
-            if key == INDEX_KEY {
-                assert!(file_types.len() == 1);
-                assert!(html_path.is_some());
-                notion_objects.push(NotionObject::OtherText {
-                    path: html_path.unwrap()
-                });
+            let invalid_combination = || PlanningError::InvalidPathCombination {
+                key: key.clone(),
+                paths: file_types.iter().map(|ft| ft.get_path().clone()).collect(),
+            };
+
+            if let (INDEX_KEY, Some(index_path)) = (key.as_str(), &html_path) {
+                if file_types.len() == 1 {
+                    notion_objects.push(NotionObject::OtherText {
+                        path: index_path.clone()
+                    });
+                } else {
+                    errors.push(invalid_combination());
+                }
                 continue;
             }
 
+            let Some((name, uuid)) = split_name_and_uuid(key) else {
+                // Without a Notion ID, these are not pages nor databases, but plain files
+                // (or a directory, which isn't renamed)
+                for file_type in file_types {
+                    if let FileType::Markdown(file_info) | FileType::Html(file_info) | FileType::Csv(file_info) | FileType::CsvAll(file_info) = file_type {
+                        notion_objects.push(NotionObject::OtherText { path: file_info.path.clone() });
+                    }
+                }
+                continue;
+            };
+
             if non_standard_file_encountered {
-                assert!(md_path.is_none());
-                assert!(html_path.is_none());
-                assert!(csv_path.is_none());
-                assert!(csv_all_path.is_none());
-                assert!(dir_path.is_none());
+                // The other files are kept as they are, but pages, databases or directories
+                // with the same key can't be told apart from them
+                if md_path.is_some() || html_path.is_some() || csv_path.is_some() || csv_all_path.is_some() || dir_path.is_some() {
+                    errors.push(invalid_combination());
+                }
                 continue;
             }
 
@@ -157,36 +180,23 @@ impl NotionObject {
                 (Some(page_path), None, None, None, dir_path)
                 // or html file
                 | (None, Some(page_path), None, None, dir_path) => {
-                    let last_space_index = key
-                        .rfind(' ')
-                        .unwrap_or_else(|| panic!("No space in file name: {}. Maybe it has already been renamed?", key));
-                    let name = key[0..last_space_index].to_string();
-                    let uuid = key[last_space_index + 1..].to_string();
                     notion_objects.push(NotionObject::Page(
-                        NotionObjectInfo::new(
-                            page_path,
-                            name,
-                            uuid,
-                            dir_path
-                        ),
+                        NotionObjectInfo {
+                            // The old name is the one of the files, whatever the form of their UUID
+                            old_name: key.clone(),
+                            ..NotionObjectInfo::new(page_path, name, uuid, dir_path)
+                        },
                         None
                     ));
                 }
                 // Database file
                 // A database file can have an associated html file
                 (None, html_file, Some(csv_path), csv_all_path, dir_path) => {
-                    let last_space_index = key
-                        .rfind(' ')
-                        .unwrap_or_else(|| panic!("No space in file name: {}. Maybe it has already been renamed?", key));
-                    let name = key[0..last_space_index].to_string();
-                    let uuid = key[last_space_index + 1..].to_string();
                     notion_objects.push(NotionObject::Database(
-                        NotionObjectInfo::new(
-                            csv_path,
-                            name,
-                            uuid,
-                            dir_path
-                        ),
+                        NotionObjectInfo {
+                            old_name: key.clone(),
+                            ..NotionObjectInfo::new(csv_path, name, uuid, dir_path)
+                        },
                         NotionDatabaseInfo {
                             csv_all_path,
                             html_path: html_file,
@@ -195,30 +205,46 @@ impl NotionObject {
                     ));
                 }
                 // A special case of a database with a csv_all file but no csv file and no html or markdown page
-                (None, None, None, Some(csv_all_path), Some(dir_path)) => panic!(
-                    "--------------------\nDatabase with csv_all file and a directory, but no csv file and no html or markdown page: KEY: {}\n\tCSV_ALL: {:?}\n\tDIR: {:?}\n-> My advice is to simply remove the '_all' at the end of {:?}. I may add proper support for this format later.\n--------------------\n",
-                    key,
+                (None, None, None, Some(csv_all_path), Some(dir_path)) => errors.push(PlanningError::CsvAllWithoutCsv {
+                    key: key.clone(),
                     csv_all_path,
                     dir_path,
-                    csv_all_path
-                ),
+                }),
                 // Directory alone. we dont rename it, so skip it.
                 // (All renamable directories are associated with a page or a database)
                 (None, None, None, None, Some(_)) => {}
                 // Invalid !
-                (md_path, html_path, csv_path, csv_all_path, dir_path) => panic!(
-                    "Invalid path combination with key [{}]):\n\tMarkdown: {:?}\n\tHTML: {:?}\n\tCSV: {:?}\n\tCSV_ALL: {:?}\n\tDIR: {:?}",
-                    key,
-                    md_path,
-                    html_path,
-                    csv_path,
-                    csv_all_path,
-                    dir_path
-                ),
+                _ => errors.push(invalid_combination()),
             }
         }
 
+        (notion_objects, errors)
+    }
+
+    /// Replaces the plain files that are pages or databases renamed by a previous run by `Cleaned` objects.
+    /// `cleaned` are the infos of these objects, with their current paths and their new names.
+    pub fn mark_cleaned_objects(notion_objects: Vec<NotionObject>, cleaned: Vec<NotionObjectInfo>) -> Vec<NotionObject> {
+        let mut cleaned_by_path: HashMap<PathBuf, NotionObjectInfo> = cleaned
+            .into_iter()
+            .map(|info| (info.path.clone(), info))
+            .collect();
+
         notion_objects
+            .into_iter()
+            .map(|object| match object {
+                NotionObject::OtherText { path } => match cleaned_by_path.remove(&path) {
+                    Some(info) => {
+                        let replacable_refs = info
+                            .new_name
+                            .as_ref()
+                            .map(|new_name| ReplacableReferencesContainer::build_from_name(&info.old_name, new_name));
+                        NotionObject::Cleaned(info, replacable_refs)
+                    }
+                    None => NotionObject::OtherText { path },
+                },
+                object => object,
+            })
+            .collect()
     }
 
     /// Returns a map of all NotionObjects by their name (without the UUID)
@@ -232,11 +258,19 @@ impl NotionObject {
     }
 }
 
+/// Splits a file key into the name and the UUID, separated by the last space.
+/// The UUID is normalized to 32 lowercase hex digits, the form of the UUIDs of Notion links once their dashes are removed.
+/// Returns None if the key doesn't end with a Notion ID.
+pub fn split_name_and_uuid(key: &str) -> Option<(String, String)> {
+    let captures = NAME_UUID_REGEX.captures(key)?;
+    Some((captures["name"].to_string(), captures["uuid"].replace('-', "").to_ascii_lowercase()))
+}
+
 // GETTERS-SETTERS
 impl NotionObject {
     pub fn get_uuid_or_invalid(&self) -> &str {
         match self {
-            NotionObject::Page(info, ..) | NotionObject::Database(info, ..) => &info.uuid,
+            NotionObject::Page(info, ..) | NotionObject::Database(info, ..) | NotionObject::Cleaned(info, ..) => &info.uuid,
            _ => "00000000000000000000000000000000"
         }
     }
@@ -244,7 +278,7 @@ impl NotionObject {
     /// Is there a directory associated with this object?
     pub fn has_dir(&self) -> bool {
         match self {
-            NotionObject::Page(info, ..) | NotionObject::Database(info, ..) => info.dir_path.is_some(),
+            NotionObject::Page(info, ..) | NotionObject::Database(info, ..) | NotionObject::Cleaned(info, ..) => info.dir_path.is_some(),
             NotionObject::OtherText { .. } | NotionObject::OtherBinary { .. } => false,
         }
     }
@@ -252,7 +286,7 @@ impl NotionObject {
     /// Gets the path to the directory associated with this object.
     pub fn get_dir(&self) -> Option<&PathBuf> {
         match self {
-            NotionObject::Page(info, ..) | NotionObject::Database(info, ..) => info.dir_path.as_ref(),
+            NotionObject::Page(info, ..) | NotionObject::Database(info, ..) | NotionObject::Cleaned(info, ..) => info.dir_path.as_ref(),
             NotionObject::OtherText { .. } | NotionObject::OtherBinary { .. } => None,
         }
     }
@@ -260,9 +294,10 @@ impl NotionObject {
     /// Gets the name of the object (without the UUID).
     pub fn get_name(&self) -> &str {
         match self {
-            NotionObject::Page(info, ..) | NotionObject::Database(info, ..) => &info.name,
+            NotionObject::Page(info, ..) | NotionObject::Database(info, ..) | NotionObject::Cleaned(info, ..) => &info.name,
+            // Paths are checked to be UTF-8 when the files are found, see `FileType::from_entry`
             NotionObject::OtherText { path, .. } | NotionObject::OtherBinary { path, .. } => {
-                path.file_stem().unwrap().to_str().unwrap()
+                path.file_stem().and_then(|stem| stem.to_str()).unwrap_or_default()
             }
         }
     }
@@ -273,105 +308,190 @@ impl NotionObject {
     }
 
     /// Sets new_name for renamable objects, ie pages and databases.
+    /// A name accepted before is replaced, with its references.
     pub fn accept_new_name(&mut self, new_name: String) {
         match self {
             NotionObject::Page(info, replace_strings) | NotionObject::Database(info, _, replace_strings) => {
                 info.new_name = Some(new_name.clone());
-
-                assert!(replace_strings.is_none());
-
                 *replace_strings = Some(ReplacableReferencesContainer::build_from_name(&info.old_name, &new_name));
             }
-            // 'Other' files don't have to be renamed
-            NotionObject::OtherText { .. } | NotionObject::OtherBinary { .. } => {}
+            // 'Other' files don't have to be renamed, cleaned objects already are
+            NotionObject::Cleaned(..) | NotionObject::OtherText { .. } | NotionObject::OtherBinary { .. } => {}
         }
     }
     
     pub fn get_path(&self) -> &PathBuf {
         match self {
-            NotionObject::Page(info, ..) | NotionObject::Database(info, ..) => &info.path,
+            NotionObject::Page(info, ..) | NotionObject::Database(info, ..) | NotionObject::Cleaned(info, ..) => &info.path,
             NotionObject::OtherText { path, .. } | NotionObject::OtherBinary { path, .. } => path,
         }
     }
-    
-    pub fn get_relative_path(&self, base: &PathBuf) -> Option<String> {
-        match self {
-            NotionObject::Page(info, ..) | NotionObject::Database(info, ..) => {
-                let relative_path = pathdiff::diff_paths(self.get_path(), base)?;
-                let relative_path_with_new_name = relative_path.to_str()?.replace(&info.old_name, info.new_name.as_ref()?);
-                Some(relative_path_with_new_name)
-            },
-            NotionObject::OtherText { .. } | NotionObject::OtherBinary { .. } => None,
-        }
+}
+
+/// Returns `path` with the file name `new_name` followed by `suffix`, keeping its extension.
+/// The extension is added rather than replaced: a new name can have a dot, like `v1.2`.
+/// A new name with a `/` places the file in subdirectories of its directory.
+fn with_new_name(path: &Path, new_name: &str, suffix: &str) -> PathBuf {
+    let mut file_name = OsString::from(format!("{}{}", new_name, suffix));
+    if let Some(extension) = path.extension() {
+        file_name.push(".");
+        file_name.push(extension);
     }
+    path.with_file_name(file_name)
 }
 
 impl NotionObject {
-
-    pub fn get_old_and_new_paths(&self) -> (PathBuf, PathBuf) {
-        let old_path = self.get_path();
+    /// Returns the new name of a page or a database, once it's accepted. Other objects are not renamed.
+    /// With a `/`, the object is placed in subdirectories of its directory, see `NameTemplate`.
+    pub fn get_new_name(&self) -> Option<&str> {
         match self {
-            NotionObject::Page(obj_info, ..) | NotionObject::Database(obj_info, ..) => {
-                let new_path = old_path
-                    .with_file_name(obj_info.new_name.as_ref().unwrap())
-                    .with_extension(old_path.extension().unwrap());
-
-                (old_path.to_owned(), new_path)
-            }
-            _ => panic!("non-page, non-database object wont be renamed"),
+            NotionObject::Page(obj_info, ..) | NotionObject::Database(obj_info, ..) => obj_info.new_name.as_deref(),
+            NotionObject::Cleaned(..) | NotionObject::OtherText { .. } | NotionObject::OtherBinary { .. } => None,
         }
     }
 
-    pub fn get_old_and_new_csv_all_paths(&self) -> Option<(PathBuf, PathBuf)> {
-        if let NotionObject::Database(
-            obj_info,
-            NotionDatabaseInfo {
-                csv_all_path: Some(old_csv_all_path),
-                ..
-            },
-            ..
-        ) = self
-        {
-            // Rename also the csv_all
-            let new_csv_all_path = old_csv_all_path
-                .with_file_name(obj_info.new_name.as_ref().unwrap().to_owned() + "_all")
-                .with_extension(old_csv_all_path.extension().unwrap());
-
-            return Some((old_csv_all_path.to_owned(), new_csv_all_path));
-        }
+    /// Returns the path of the file of a page or a database, and its path with its new name.
+    /// None if the object is not renamed, see `get_new_name`.
+    pub fn get_old_and_new_paths(&self) -> Option<(PathBuf, PathBuf)> {
+        let old_path = self.get_path();
+        let new_path = with_new_name(old_path, self.get_new_name()?, "");
+        Some((old_path.to_owned(), new_path))
+    }
 
-        None
+    pub fn get_old_and_new_csv_all_paths(&self) -> Option<(PathBuf, PathBuf)> {
+        // Rename also the csv_all
+        let NotionObject::Database(_, NotionDatabaseInfo { csv_all_path: Some(old_csv_all_path), .. }, ..) = self else {
+            return None;
+        };
+        let new_csv_all_path = with_new_name(old_csv_all_path, self.get_new_name()?, "_all");
+        Some((old_csv_all_path.to_owned(), new_csv_all_path))
     }
 
     pub fn get_old_and_new_html_paths(&self) -> Option<(PathBuf, PathBuf)> {
-        if let NotionObject::Database(
-            obj_info,
-            NotionDatabaseInfo {
-                html_path: Some(old_html_path),
-                ..
-            },
-            ..
-        ) = self
-        {
-            // Rename also the html
-            let new_html_path = old_html_path
-                .with_file_name(obj_info.new_name.as_ref().unwrap())
-                .with_extension(old_html_path.extension().unwrap());
-
-            return Some((old_html_path.to_owned(), new_html_path));
+        // Rename also the html
+        let NotionObject::Database(_, NotionDatabaseInfo { html_path: Some(old_html_path), .. }, ..) = self else {
+            return None;
+        };
+        let new_html_path = with_new_name(old_html_path, self.get_new_name()?, "");
+        Some((old_html_path.to_owned(), new_html_path))
+    }
+
+    /// Returns all the paths the object would have with `new_name`: its file,
+    /// the csv_all and html files of a database, and its directory.
+    pub fn get_paths_with_new_name(&self, new_name: &str) -> Vec<PathBuf> {
+        let mut new_paths = vec![with_new_name(self.get_path(), new_name, "")];
+        if let NotionObject::Database(_, NotionDatabaseInfo { csv_all_path, html_path }, ..) = self {
+            new_paths.extend(csv_all_path.iter().map(|path| with_new_name(path, new_name, "_all")));
+            new_paths.extend(html_path.iter().map(|path| with_new_name(path, new_name, "")));
+        }
+        if let Some(dir_path) = self.get_dir() {
+            new_paths.push(dir_path.with_file_name(new_name));
         }
 
-        None
+        new_paths
     }
 
-    pub fn get_old_and_new_dir_paths(&self) -> (PathBuf, PathBuf) {
-        let old_dir_path = self.get_dir().unwrap();
-        match self {
-            NotionObject::Page(obj_info, ..) | NotionObject::Database(obj_info, ..) => {
-                let new_dir_path = old_dir_path.with_file_name(obj_info.new_name.as_ref().unwrap());
-                (old_dir_path.to_owned(), new_dir_path)
-            }
-            _ => panic!("non-page, non-database object dont have a directory"),
+    /// Returns the directory of a page or a database, and its path with its new name.
+    /// None if the object has no directory, or is not renamed, see `get_new_name`.
+    pub fn get_old_and_new_dir_paths(&self) -> Option<(PathBuf, PathBuf)> {
+        let old_dir_path = self.get_dir()?;
+        let new_dir_path = old_dir_path.with_file_name(self.get_new_name()?);
+        Some((old_dir_path.to_owned(), new_dir_path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn database(new_name: Option<&str>) -> NotionObject {
+        let mut info = NotionObjectInfo::new(
+            PathBuf::from("export/Tasks 1.csv"),
+            "Tasks".to_string(),
+            "1".to_string(),
+            Some(PathBuf::from("export/Tasks 1")),
+        );
+        info.new_name = new_name.map(str::to_string);
+        let database_info = NotionDatabaseInfo {
+            csv_all_path: Some(PathBuf::from("export/Tasks 1_all.csv")),
+            html_path: Some(PathBuf::from("export/Tasks 1.html")),
+        };
+        NotionObject::Database(info, database_info, None)
+    }
+
+    #[test]
+    fn renames_all_the_paths_of_a_database() {
+        let database = database(Some("Tasks v1.2"));
+        let new_path = |rename: Option<(PathBuf, PathBuf)>| rename.unwrap().1;
+
+        assert_eq!(new_path(database.get_old_and_new_paths()), Path::new("export/Tasks v1.2.csv"));
+        assert_eq!(new_path(database.get_old_and_new_csv_all_paths()), Path::new("export/Tasks v1.2_all.csv"));
+        assert_eq!(new_path(database.get_old_and_new_html_paths()), Path::new("export/Tasks v1.2.html"));
+        assert_eq!(new_path(database.get_old_and_new_dir_paths()), Path::new("export/Tasks v1.2"));
+        assert_eq!(
+            database.get_paths_with_new_name("Tasks v1.2"),
+            ["export/Tasks v1.2.csv", "export/Tasks v1.2_all.csv", "export/Tasks v1.2.html", "export/Tasks v1.2"].map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn does_not_rename_objects_without_a_new_name() {
+        let database = database(None);
+        assert!(database.get_old_and_new_paths().is_none());
+        assert!(database.get_old_and_new_csv_all_paths().is_none());
+        assert!(database.get_old_and_new_html_paths().is_none());
+        assert!(database.get_old_and_new_dir_paths().is_none());
+
+        let file = NotionObject::OtherText { path: PathBuf::from("export/notes.txt") };
+        assert!(file.get_old_and_new_paths().is_none());
+        assert!(file.get_old_and_new_dir_paths().is_none());
+    }
+
+    #[test]
+    fn marks_the_objects_cleaned_by_a_previous_run() {
+        let mut info = NotionObjectInfo::new(PathBuf::from("export/Home.md"), "Home".to_string(), "1".to_string(), None);
+        info.new_name = Some("Home".to_string());
+        let objects = vec![
+            NotionObject::OtherText { path: PathBuf::from("export/Home.md") },
+            NotionObject::OtherText { path: PathBuf::from("export/notes.txt") },
+        ];
+
+        let objects = NotionObject::mark_cleaned_objects(objects, vec![info]);
+        assert!(matches!(&objects[0], NotionObject::Cleaned(info, Some(_)) if info.old_name == "Home 1"));
+        assert!(matches!(&objects[1], NotionObject::OtherText { .. }));
+        // Cleaned objects are not renamed again
+        assert!(!objects[0].is_page_or_dataset());
+        assert!(objects[0].get_old_and_new_paths().is_none());
+    }
+
+    /// Makes the objects of the files at `paths`, all text files, with the errors of the files left out.
+    fn create_objects(paths: &[&str]) -> (Vec<NotionObject>, Vec<PlanningError>) {
+        let mut file_map: FileMapByName = HashMap::new();
+        for path in paths {
+            let file_type = FileType::from_entry(PathBuf::from(path), false, || Ok(String::new())).unwrap();
+            file_map.entry(file_type.get_file_key().to_string()).or_default().push(file_type);
         }
+        NotionObject::create_objects_from_file_map(&file_map)
+    }
+
+    #[test]
+    fn treats_files_without_a_notion_id_as_plain_files() {
+        let (objects, errors) = create_objects(&["export/Meeting 2024.md", "export/Home.md", "export/Tasks 12345678.csv", "export/notes"]);
+        assert!(errors.is_empty());
+        assert_eq!(objects.len(), 4);
+        assert!(objects.iter().all(|object| matches!(object, NotionObject::OtherText { .. })));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn normalizes_dashed_and_uppercase_notion_ids() {
+        let (objects, errors) = create_objects(&["export/Home 1111ABCD-1111-1111-1111-111111111111.md"]);
+        assert!(errors.is_empty());
+        let [NotionObject::Page(info, _)] = objects.as_slice() else {
+            panic!("expected a page");
+        };
+        assert_eq!(info.name, "Home");
+        assert_eq!(info.uuid, "1111abcd111111111111111111111111");
+        // References to the page are still found with the name of its files
+        assert_eq!(info.old_name, "Home 1111ABCD-1111-1111-1111-111111111111");
+    }
+}
@@ -1,134 +1,505 @@
-use std::{collections::HashSet, fs};
+use std::{collections::{HashMap, HashSet}, path::{Component, Path, PathBuf}};
 
-use indicatif::ProgressIterator;
+use chrono::NaiveDateTime;
 
-use crate::{constants::PROGRESS_BAR_STYLE, notion_object::{NotionObject, ObjectsMapByName}};
+use crate::{error::TemplateError, manifest::NameManifest, naming::{self, NamingOptions, TemplateValues}, notion_object::{NotionObject, ObjectsMapByName}};
 
 /// Find a new name for objects.
+/// Names are made with `NamingOptions::name_template`, the creation times of the pages being in `creation_times`,
+/// then made valid file names following `options`, see `NamingOptions::sanitize`.
+/// Objects keep the name they had in a previous run, as recorded in `previous_names`, if it's still available.
+/// Then each object gets its name if it's available, and the others get a suffix: `name 1`, `name 2`...
+/// A name is available if none of the paths the object would have with it (see `NotionObject::get_paths_with_new_name`)
+/// is taken by another object, or by one of the `existing_paths`: all files and directories of the export, ignored ones included.
+/// With `NamingOptions::insensitive_collisions`, names that only differ by their case or their Unicode normalization are the same.
+/// Names too long for `NamingOptions::max_name_bytes` or `NamingOptions::max_path_bytes` are truncated,
+/// the paths being measured from `root`, the root of the export, once the parent directories are renamed.
+/// Objects are named in the order of their depth, then of their names, then of their UUIDs, to ensure determinism.
+/// Fails if the template can't give the name of an object, see `NameTemplate::render`.
 /// ASSUMPTION: No directory can exist without a page or a database.
 /// This assumption has been checked in `objects_from_map`, which makes sure either a page or a database exists for each entry.
-pub fn resolve_new_names(all_objects_by_name: &mut ObjectsMapByName) {
-    for (name, objects) in all_objects_by_name {
-        if objects.len() == 1 {
-            // This object is the only one to want this name, so we can use it
-            objects[0].accept_new_name(name.clone());
-            continue;
-        }
+pub fn resolve_new_names(
+    all_objects_by_name: &mut ObjectsMapByName,
+    previous_names: &NameManifest,
+    root: &Path,
+    existing_paths: &[PathBuf],
+    creation_times: &HashMap<PathBuf, NaiveDateTime>,
+    options: &NamingOptions,
+) -> Result<(), TemplateError> {
+    let mut groups = all_objects_by_name.iter_mut().collect::<Vec<_>>();
+    groups.sort_by_key(|(name, _)| *name);
+
+    let mut objects = vec![];
+    for (_, group) in groups {
+        // Sort by uuid to ensure determinism
+        group.sort_by(|left_obj, right_obj| left_obj.get_uuid_or_invalid().cmp(right_obj.get_uuid_or_invalid()));
+        objects.extend(group.iter_mut());
+    }
+
+    // paths that we expect after renaming the files (not touching the parent directories)
+    // e.g. for file "/parent page 15278/page 579632.md", the expected path is "/parent page 15278/page.md"
+    // it's used to see if there are conflicts that need a suffix
+    // Entries already on disk (plain files, ignored files, objects cleaned by a previous run) must not be overwritten
+    let mut new_paths_seen = existing_paths
+        .iter()
+        .chain(objects.iter().filter(|obj| !obj.is_page_or_dataset()).map(|obj| obj.get_path()))
+        .map(|path| options.get_collision_key(path))
+        .collect::<HashSet<PathBuf>>();
+
+    // Only pages and databases are renamed: there's no uuid in the other files
+    let mut objects = objects.into_iter().filter(|obj| obj.is_page_or_dataset()).collect::<Vec<_>>();
 
-        // paths that we expect after renaming the files (not touching the directories)
-        // e.g. for file "/parent page 15278/page 579632.md", the expected path is "/parent page 15278/page.md"
-        // it's used to see if there are conflicts that need a suffix
-        let paths_after_files_renamed = objects
+    // Parents are named before their children, so that the length of their new path is known.
+    // Objects of the same directory have the same depth: they keep their order, and only compete with each other.
+    objects.sort_by_key(|obj| obj.get_path().components().count());
+    let mut renamed_dirs = HashMap::new();
+    for level in objects.chunk_by_mut(|left_obj, right_obj| left_obj.get_path().components().count() == right_obj.get_path().components().count()) {
+        let parents = level
             .iter()
             .map(|obj| {
-                if !obj.is_page_or_dataset() {
-                    // there's no uuid in these files
-                    // they wont be renamed!
-                    return None;
-                }
+                let parent = obj.get_path().parent().unwrap(); // Should not panic, objects are in the root
+                strip_root(&get_final_path(parent, &renamed_dirs), root)
+            })
+            .collect::<Vec<PathBuf>>();
+        resolve_level_new_names(level, &parents, previous_names, creation_times, &mut new_paths_seen, options)?;
 
-                let path = obj.get_path();
-                let extension = path.extension().unwrap().to_str().unwrap();
+        renamed_dirs.extend(level.iter().filter_map(|obj| obj.get_old_and_new_dir_paths()));
+    }
 
-                let mut new_name_with_extension = name.clone();
-                new_name_with_extension.push('.');
-                new_name_with_extension.push_str(extension);
+    Ok(())
+}
 
-                Some(path.with_file_name(new_name_with_extension))
+/// Names the `objects` of one depth, whose parent directories will have the paths `parents`, see `resolve_new_names`.
+fn resolve_level_new_names(
+    objects: &mut [&mut NotionObject],
+    parents: &[PathBuf],
+    previous_names: &NameManifest,
+    creation_times: &HashMap<PathBuf, NaiveDateTime>,
+    new_paths_seen: &mut HashSet<PathBuf>,
+    options: &NamingOptions,
+) -> Result<(), TemplateError> {
+    let templated_names = objects
+        .iter()
+        .zip(parents)
+        .map(|(obj, parent)| {
+            options.name_template.render(&TemplateValues {
+                name: obj.get_name(),
+                uuid: obj.get_uuid_or_invalid(),
+                parent: parent.file_name().and_then(|name| name.to_str()).unwrap_or_default(),
+                created: creation_times.get(obj.get_path()).copied(),
             })
-            .collect::<Vec<_>>();
+        })
+        .collect::<Result<Vec<String>, TemplateError>>()?;
+    let names = templated_names.iter().map(|name| options.sanitize(name)).collect::<Vec<String>>();
+    let previous_names = objects
+        .iter()
+        .map(|obj| previous_names.get_previous_name(obj).map(|name| options.sanitize(name)))
+        .collect::<Vec<Option<String>>>();
+    let mut is_named = vec![false; objects.len()];
 
-        // Sort by uuid to ensure determinism
-        objects.sort_by(|left_obj, right_obj| left_obj.get_uuid_or_invalid().cmp(&right_obj.get_uuid_or_invalid()));
-        
-        let mut new_paths_seen = HashSet::new();
-        for i in 0..objects.len() {
-            let obj = &mut objects[i];
-            match paths_after_files_renamed[i].as_ref() {
-                // This object is not a page or a database, so we don't need to rename it
-                None => continue,
-                Some(desired_path) => {
-                    let mut desired_path = desired_path.clone();
-
-                    let mut add = 1;
-                    while new_paths_seen.contains(&desired_path) {
-                        // This exact path already exists, so we need to add a number to the end of the name
-                        desired_path = desired_path
-                            .with_file_name(
-                                format!("{} {}", name, add), // No extension at this point, with_file_name removes it
-                            )
-                            .with_extension(desired_path.extension().unwrap_or_default());
-                        add += 1;
-                    }
-
-                    // pfew! exiting the loop, we found a name that doesn't conflict with any other
-                    new_paths_seen.insert(desired_path.clone());
-
-                    // Sets the new name!
-                    obj.accept_new_name(
-                        desired_path
-                            .file_stem()
-                            .unwrap()
-                            .to_str()
-                            .unwrap()
-                            .to_string(),
-                    );
-                }
-            }
+    // The directories the pages are placed in, with a `/` in their names, can't be the paths of the objects of this level
+    let reserved_dirs = objects
+        .iter()
+        .zip(names.iter().zip(&previous_names))
+        .flat_map(|(obj, (name, previous_name))| {
+            let parent = obj.get_path().parent().unwrap(); // Should not panic, objects are in the root
+            [Some(name), previous_name.as_ref()].into_iter().flatten().flat_map(move |name| get_name_directories(parent, name))
+        })
+        .map(|dir| options.get_collision_key(&dir))
+        .collect::<HashSet<PathBuf>>();
+
+    // Objects named in a previous run first, so that they keep their name
+    for (i, obj) in objects.iter_mut().enumerate() {
+        let Some(previous_name) = &previous_names[i] else {
+            continue;
+        };
+
+        let previous_name = get_name_within_limits(obj, &parents[i], previous_name, "", options);
+        if take_paths_with_new_name(new_paths_seen, &reserved_dirs, obj, &previous_name, options) {
+            obj.accept_new_name(previous_name);
+            is_named[i] = true;
         }
     }
-}
 
-/// Renames all files associated with all given objects.
-/// Associated files are the csv_all and the html files for databases. NOT the directories.
-pub fn rename_objects_files(all_objects: &Vec<&NotionObject>, is_test: bool) {
-    for object in all_objects
-        .iter()
-        .progress_with_style(PROGRESS_BAR_STYLE.clone())
-        .filter(|obj| obj.is_page_or_dataset())
-    {
-        let (old_path, new_path) = object.get_old_and_new_paths();
-        
-        if !is_test  {
-            fs::rename(old_path, new_path).unwrap(); // Should not panic
+    // Then objects that can have their name as it is, starting with the ones whose name didn't need to be sanitized
+    let mut order = (0..objects.len()).collect::<Vec<usize>>();
+    order.sort_by_key(|&i| names[i] != templated_names[i]);
+    for i in order {
+        if is_named[i] {
+            continue;
         }
 
-        if let Some((old_csv_all_path, new_csv_all_path)) = object.get_old_and_new_csv_all_paths()
-        {
-            if !is_test{
-                fs::rename(old_csv_all_path, new_csv_all_path).unwrap(); // Should not panic
-            }
+        let obj = &mut objects[i];
+        let new_name = get_name_within_limits(obj, &parents[i], &names[i], "", options);
+        if take_paths_with_new_name(new_paths_seen, &reserved_dirs, obj, &new_name, options) {
+            obj.accept_new_name(new_name);
+            is_named[i] = true;
         }
+    }
 
-        if let Some((old_html_path, new_html_path)) = object.get_old_and_new_html_paths() {
-            if !is_test{
-                fs::rename(old_html_path, new_html_path).unwrap(); // Should not panic
-            }
+    for (i, obj) in objects.iter_mut().enumerate() {
+        if is_named[i] {
+            continue;
+        }
+
+        let mut add = 1;
+        let mut new_name = get_name_within_limits(obj, &parents[i], &names[i], &format!(" {}", add), options);
+        // This exact path already exists, so we need to add a number to the end of the name
+        while !take_paths_with_new_name(new_paths_seen, &reserved_dirs, obj, &new_name, options) {
+            add += 1;
+            new_name = get_name_within_limits(obj, &parents[i], &names[i], &format!(" {}", add), options);
         }
+
+        // pfew! exiting the loop, we found a name that doesn't conflict with any other
+        obj.accept_new_name(new_name);
+    }
+
+    Ok(())
+}
+
+/// Returns `name` followed by `suffix`, with `name` truncated so that all the paths of `obj` fit in the limits of `options`.
+/// `parent` is the path the parent directory of `obj` will have, relative to the root of the export.
+/// The directories of a name with a `/` are never truncated, see `naming::truncate`.
+fn get_name_within_limits(obj: &NotionObject, parent: &Path, name: &str, suffix: &str, options: &NamingOptions) -> String {
+    let old_parent = obj.get_path().parent().unwrap(); // Should not panic, objects are in the root
+    let mut name = name.to_string();
+    loop {
+        let new_name = format!("{}{}", name, suffix);
+        let excess_bytes = obj
+            .get_paths_with_new_name(&new_name)
+            .iter()
+            .map(|path| options.get_excess_bytes(parent, path.strip_prefix(old_parent).unwrap())) // Should not panic, the paths are in the same directory
+            .max()
+            .unwrap_or(0);
+
+        let truncated = naming::truncate(&name, excess_bytes);
+        // A name of one character can't be shorter, even if it's still too long
+        if excess_bytes == 0 || truncated == name {
+            return new_name;
+        }
+        name = truncated;
     }
 }
 
-/// Renames all directories associated with all given objects.
+/// Adds the paths `obj` would have with `new_name` to `new_paths_seen`, if none of them is already there, or in `reserved_dirs`.
+/// Returns whether they were added, ie whether `new_name` is available.
+fn take_paths_with_new_name(
+    new_paths_seen: &mut HashSet<PathBuf>,
+    reserved_dirs: &HashSet<PathBuf>,
+    obj: &NotionObject,
+    new_name: &str,
+    options: &NamingOptions,
+) -> bool {
+    let new_paths = obj
+        .get_paths_with_new_name(new_name)
+        .iter()
+        .map(|path| options.get_collision_key(path))
+        .collect::<Vec<PathBuf>>();
+    if new_paths.iter().any(|path| new_paths_seen.contains(path) || reserved_dirs.contains(path)) {
+        return false;
+    }
+
+    new_paths_seen.extend(new_paths);
+    true
+}
+
+/// Returns the directories an object of the directory `parent` is placed in with `new_name`, from the shallowest:
+/// `2024/01/Meeting` gives `parent/2024` and `parent/2024/01`.
+fn get_name_directories(parent: &Path, new_name: &str) -> Vec<PathBuf> {
+    let mut directories = vec![];
+    let mut directory = parent.to_path_buf();
+    if let Some((name_directories, _)) = new_name.rsplit_once('/') {
+        for name_directory in name_directories.split('/') {
+            directory.push(name_directory);
+            directories.push(directory.clone());
+        }
+    }
+    directories
+}
+
+/// Returns the directories to create for the renames of all given objects, which place them in subdirectories of their directory,
+/// see `NameTemplate`. Directories that are in `existing_paths` are not created again.
+/// Their paths are the ones they have before their parents are renamed, from the shallowest to the deepest,
+/// so that they can be created before the renames.
+pub fn get_created_dirs(all_objects: &Vec<&NotionObject>, existing_paths: &[PathBuf]) -> Vec<PathBuf> {
+    let existing_paths = existing_paths.iter().collect::<HashSet<&PathBuf>>();
+    let mut created_dirs = all_objects
+        .iter()
+        .filter_map(|obj| Some((obj.get_path().parent()?, obj.get_new_name()?)))
+        .flat_map(|(parent, new_name)| get_name_directories(parent, new_name))
+        .filter(|dir| !existing_paths.contains(dir))
+        .collect::<HashSet<PathBuf>>()
+        .into_iter()
+        .collect::<Vec<PathBuf>>();
+    created_dirs.sort_by(|left, right| (left.components().count(), left).cmp(&(right.components().count(), right)));
+    created_dirs
+}
+
+/// Lists the renames of all files associated with all given objects.
+/// Associated files are the csv_all and the html files for databases. NOT the directories.
+pub fn get_objects_files_renames(all_objects: &Vec<&NotionObject>) -> Vec<(PathBuf, PathBuf)> {
+    let mut renames = vec![];
+
+    for object in all_objects.iter().filter(|obj| obj.is_page_or_dataset()) {
+        renames.extend(object.get_old_and_new_paths());
+        renames.extend(object.get_old_and_new_csv_all_paths());
+        renames.extend(object.get_old_and_new_html_paths());
+    }
+
+    renames
+}
+
+/// Lists the renames of all directories associated with all given objects.
 /// Sorts the directories by their depth (deepest first) to avoid conflicts.
 /// Indeed, renaming a parent directory first would invalidate the child path.
-pub fn rename_directories(all_objects: &Vec<&NotionObject>, is_test: bool) {
-    let mut all_objects_sorted_by_dir_path_len_highest_first = all_objects
+pub fn get_directories_renames(all_objects: &Vec<&NotionObject>) -> Vec<(PathBuf, PathBuf)> {
+    let mut renames_sorted_by_dir_path_len_highest_first = all_objects
         .iter()
-        .filter(|obj| obj.has_dir())
-        .copied()
-        .collect::<Vec<&NotionObject>>();
-    all_objects_sorted_by_dir_path_len_highest_first
-        .sort_by_key(|obj| obj.get_dir().unwrap().components().count());
-    all_objects_sorted_by_dir_path_len_highest_first.reverse();
-
-    for object in all_objects_sorted_by_dir_path_len_highest_first
-        .iter()
-        .progress_with_style(PROGRESS_BAR_STYLE.clone())
-    {
-        let (old_dir_path, new_dir_path) = object.get_old_and_new_dir_paths();
+        .filter(|obj| obj.is_page_or_dataset())
+        .filter_map(|obj| obj.get_old_and_new_dir_paths())
+        .collect::<Vec<(PathBuf, PathBuf)>>();
+    renames_sorted_by_dir_path_len_highest_first.sort_by_key(|(old_dir_path, _)| old_dir_path.components().count());
+    renames_sorted_by_dir_path_len_highest_first.reverse();
 
-        if !is_test {
-            fs::rename(old_dir_path, new_dir_path).unwrap(); // Should not panic
+    renames_sorted_by_dir_path_len_highest_first
+}
+
+/// Returns the new path of every file and directory that will be renamed, by old path.
+/// The new paths only differ from the old ones by their file name, and the subdirectories it can be in: parents are not renamed.
+pub fn build_renamed_paths(all_objects: &Vec<&NotionObject>) -> HashMap<PathBuf, PathBuf> {
+    get_objects_files_renames(all_objects)
+        .into_iter()
+        .chain(get_directories_renames(all_objects))
+        .collect()
+}
+
+/// Returns the path `path` will have once itself and all its parent directories are renamed.
+pub fn get_final_path(path: &Path, renamed_paths: &HashMap<PathBuf, PathBuf>) -> PathBuf {
+    let mut old_path = PathBuf::new();
+    let mut final_path = PathBuf::new();
+
+    for component in path.components() {
+        let parent = old_path.clone();
+        old_path.push(component);
+        // The new name can be in subdirectories of the parent, see `NameTemplate`
+        match renamed_paths.get(&old_path).and_then(|new_path| new_path.strip_prefix(&parent).ok()) {
+            Some(new_name) => final_path.push(new_name),
+            None => final_path.push(component),
         }
     }
-}
\ No newline at end of file
+
+    final_path
+}
+
+/// Returns `path` relative to `root`.
+pub fn strip_root(path: &Path, root: &Path) -> PathBuf {
+    path.strip_prefix(root).unwrap().to_path_buf() // Should not panic, all paths are in the root
+}
+
+/// Resolves the `.` and `..` components of `path`, without looking at the file system.
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if matches!(normalized.components().next_back(), Some(Component::Normal(_))) => {
+                normalized.pop();
+            }
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{naming::SanitizeNames, notion_object::NotionObjectInfo};
+
+    use super::*;
+
+    /// Returns the page `name uuid.md` in `dir`, with its directory if `has_dir`.
+    fn page(dir: &str, name: &str, uuid: &str, has_dir: bool) -> NotionObject {
+        let dir = Path::new(dir);
+        let path = dir.join(format!("{} {}.md", name, uuid));
+        let dir_path = has_dir.then(|| dir.join(format!("{} {}", name, uuid)));
+        NotionObject::Page(NotionObjectInfo::new(path, name.to_string(), uuid.to_string(), dir_path), None)
+    }
+
+    /// Names `objects`, and returns the new name of each of them, by UUID.
+    fn resolve(
+        objects: Vec<NotionObject>,
+        previous_names: &NameManifest,
+        existing_paths: &[PathBuf],
+        options: &NamingOptions,
+    ) -> HashMap<String, String> {
+        let mut objects_map = NotionObject::build_map_by_name(objects);
+        resolve_new_names(&mut objects_map, previous_names, Path::new("export"), existing_paths, &HashMap::new(), options).unwrap();
+
+        objects_map
+            .values()
+            .flatten()
+            .filter_map(|obj| match obj {
+                NotionObject::Page(info, ..) => Some((info.uuid.clone(), info.new_name.clone()?)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn names<const N: usize>(names: [(&str, &str); N]) -> HashMap<String, String> {
+        names.into_iter().map(|(uuid, name)| (uuid.to_string(), name.to_string())).collect()
+    }
+
+    #[test]
+    fn gives_suffixes_in_the_order_of_the_uuids() {
+        let objects = vec![page("export", "Notes", "2", false), page("export", "Notes", "1", false), page("export", "Tasks", "3", false)];
+        let new_names = resolve(objects, &NameManifest::default(), &[], &NamingOptions::default());
+        assert_eq!(new_names, names([("1", "Notes"), ("2", "Notes 1"), ("3", "Tasks")]));
+    }
+
+    #[test]
+    fn keeps_the_names_of_a_previous_run() {
+        let mut previous_run = [page("export", "Notes", "2", false), page("export", "Notes", "3", false)];
+        previous_run[0].accept_new_name("Notes".to_string());
+        previous_run[1].accept_new_name("Notes 1".to_string());
+        let mut manifest = NameManifest::default();
+        manifest.update(&previous_run.iter().collect());
+
+        // A page with a smaller UUID appears: the pages named before keep their names
+        let objects = vec![page("export", "Notes", "1", false), page("export", "Notes", "2", false), page("export", "Notes", "3", false)];
+        let new_names = resolve(objects, &manifest, &[], &NamingOptions::default());
+        assert_eq!(new_names, names([("1", "Notes 2"), ("2", "Notes"), ("3", "Notes 1")]));
+    }
+
+    #[test]
+    fn prefers_names_that_did_not_need_to_be_sanitized() {
+        let options = NamingOptions { sanitize_names: SanitizeNames::Portable, ..Default::default() };
+        let objects = vec![page("export", "Q1: plan", "1", false), page("export", "Q1_ plan", "2", false), page("export", "CON", "3", false)];
+        let new_names = resolve(objects, &NameManifest::default(), &[], &options);
+        assert_eq!(new_names, names([("1", "Q1_ plan 1"), ("2", "Q1_ plan"), ("3", "CON_")]));
+    }
+
+    #[test]
+    fn finds_collisions_ignoring_case_and_normalization_only_if_asked() {
+        let objects = || {
+            [("Notes", "1"), ("notes", "2"), ("Caf\u{e9}", "3"), ("Cafe\u{301}", "4")]
+                .into_iter()
+                .map(|(name, uuid)| page("export", name, uuid, false))
+                .collect::<Vec<_>>()
+        };
+
+        let new_names = resolve(objects(), &NameManifest::default(), &[], &NamingOptions::default());
+        assert_eq!(new_names, names([("1", "Notes"), ("2", "notes"), ("3", "Caf\u{e9}"), ("4", "Cafe\u{301}")]));
+
+        let options = NamingOptions { insensitive_collisions: true, ..Default::default() };
+        let new_names = resolve(objects(), &NameManifest::default(), &[], &options);
+        // Objects are named in the order of their names: `Cafe\u{301}` comes before `Caf\u{e9}`
+        assert_eq!(new_names, names([("1", "Notes"), ("2", "notes 1"), ("3", "Caf\u{e9} 1"), ("4", "Cafe\u{301}")]));
+    }
+
+    #[test]
+    fn never_takes_the_paths_of_existing_files_and_directories() {
+        let existing_paths = ["export/Notes.md", "export/Tasks", "export/readme.md"].map(PathBuf::from);
+        let objects = || {
+            vec![
+                page("export", "Notes", "1", false),
+                page("export", "Tasks", "2", true),
+                page("export", "Ideas", "3", false),
+                page("export", "README", "4", false),
+            ]
+        };
+
+        let new_names = resolve(objects(), &NameManifest::default(), &existing_paths, &NamingOptions::default());
+        assert_eq!(new_names, names([("1", "Notes 1"), ("2", "Tasks 1"), ("3", "Ideas"), ("4", "README")]));
+
+        let options = NamingOptions { insensitive_collisions: true, ..Default::default() };
+        let new_names = resolve(objects(), &NameManifest::default(), &existing_paths, &options);
+        assert_eq!(new_names, names([("1", "Notes 1"), ("2", "Tasks 1"), ("3", "Ideas"), ("4", "README 1")]));
+    }
+
+    #[test]
+    fn never_takes_the_paths_of_other_files_of_the_export() {
+        let objects = vec![NotionObject::OtherText { path: PathBuf::from("export/Notes.md") }, page("export", "Notes", "1", false)];
+        let new_names = resolve(objects, &NameManifest::default(), &[], &NamingOptions::default());
+        assert_eq!(new_names, names([("1", "Notes 1")]));
+    }
+
+    #[test]
+    fn truncates_names_too_long_for_max_name_bytes() {
+        let options = NamingOptions { max_name_bytes: Some(8), ..Default::default() };
+        let objects = vec![
+            page("export", "Meeting notes A", "1", false),
+            page("export", "Meeting notes B", "2", false),
+            page("export", "Ideas", "3", false),
+        ];
+        let new_names = resolve(objects, &NameManifest::default(), &[], &options);
+        // The suffix is kept, the name is truncated to make room for it
+        assert_eq!(new_names, names([("1", "Meeti"), ("2", "Mee 1"), ("3", "Ideas")]));
+    }
+
+    #[test]
+    fn truncates_names_too_long_for_max_path_bytes_once_the_parents_are_renamed() {
+        let options = NamingOptions { max_path_bytes: Some(20), ..Default::default() };
+        let objects = vec![page("export", "Projects", "1", true), page("export/Projects 1", "Roadmap 2026", "2", false)];
+        let new_names = resolve(objects, &NameManifest::default(), &[], &options);
+        // `Projects/Roadmap.md` is 19 bytes: the trailing space is removed
+        assert_eq!(new_names, names([("1", "Projects"), ("2", "Roadmap")]));
+    }
+
+    #[test]
+    fn places_pages_in_directories_named_after_their_parent() {
+        let options = NamingOptions { name_template: "{parent}/{name}".parse().unwrap(), ..Default::default() };
+        let objects = vec![
+            page("export", "Home", "1", true),
+            page("export/Home 1", "Child", "2", true),
+            page("export/Home 1/Child 2", "Grandchild", "3", false),
+        ];
+        let new_names = resolve(objects, &NameManifest::default(), &[], &options);
+        assert_eq!(new_names, names([("1", "Home"), ("2", "Home/Child"), ("3", "Child/Grandchild")]));
+    }
+
+    /// Names `objects` with `template`, `Meeting 1.md` of `export/Tasks` being created on January 5, 2024.
+    fn resolve_rows(objects: Vec<NotionObject>, template: &str) -> ObjectsMapByName {
+        let options = NamingOptions { name_template: template.parse().unwrap(), ..Default::default() };
+        let created = chrono::NaiveDate::from_ymd_opt(2024, 1, 5).and_then(|date| date.and_hms_opt(15, 4, 0)).unwrap();
+        let creation_times = HashMap::from([(PathBuf::from("export/Tasks/Meeting 1.md"), created)]);
+
+        let mut objects_map = NotionObject::build_map_by_name(objects);
+        resolve_new_names(&mut objects_map, &NameManifest::default(), Path::new("export"), &[], &creation_times, &options).unwrap();
+        objects_map
+    }
+
+    #[test]
+    fn places_database_rows_in_directories_by_creation_date() {
+        let objects = vec![page("export/Tasks", "Meeting", "1", false), page("export/Tasks", "Ideas", "2", true)];
+        let objects_map = resolve_rows(objects, "{created:%Y}/{created:%d.%m.%Y} {name}");
+        let objects = objects_map.values().flatten().collect::<Vec<&NotionObject>>();
+        let renamed_paths = build_renamed_paths(&objects);
+
+        // The dots of the date are not taken for the extension
+        assert_eq!(renamed_paths[Path::new("export/Tasks/Meeting 1.md")], Path::new("export/Tasks/2024/05.01.2024 Meeting.md"));
+        // Without a creation time, the row stays where it is
+        assert_eq!(renamed_paths[Path::new("export/Tasks/Ideas 2.md")], Path::new("export/Tasks/Ideas.md"));
+        assert_eq!(get_created_dirs(&objects, &[]), [PathBuf::from("export/Tasks/2024")]);
+        assert_eq!(get_created_dirs(&objects, &[PathBuf::from("export/Tasks/2024")]), Vec::<PathBuf>::new());
+    }
+
+    #[test]
+    fn never_gives_an_object_the_path_of_a_directory_pages_are_placed_in() {
+        let objects = vec![page("export/Tasks", "Meeting", "1", false), page("export/Tasks", "2024", "2", true)];
+        let objects_map = resolve_rows(objects, "{created:%Y}/{name}");
+
+        let new_names = objects_map.values().flatten().map(|obj| obj.get_new_name().unwrap()).collect::<HashSet<&str>>();
+        // The directory of `2024` would be the one `Meeting` is placed in
+        assert_eq!(new_names, HashSet::from(["2024/Meeting", "2024 1"]));
+    }
+
+    #[test]
+    fn finds_the_final_paths_of_files_placed_in_subdirectories() {
+        let renamed_paths = HashMap::from([
+            (PathBuf::from("export/Home 1"), PathBuf::from("export/2024/Home")),
+            (PathBuf::from("export/Home 1/Child 2.md"), PathBuf::from("export/Home 1/Child.md")),
+        ]);
+        assert_eq!(get_final_path(Path::new("export/Home 1/Child 2.md"), &renamed_paths), Path::new("export/2024/Home/Child.md"));
+        assert_eq!(get_final_path(Path::new("export/Home 1/image.png"), &renamed_paths), Path::new("export/2024/Home/image.png"));
+    }
+}
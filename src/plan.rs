@@ -0,0 +1,87 @@
+use std::{
+    cmp::Reverse,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+
+use crate::{
+    content_replacing::{RenamedContents, Replacement},
+    notion_object::NotionObject,
+    path_replacing,
+    undo::PathRename,
+};
+
+/// The replacements planned in a file.
+#[derive(Debug, Serialize)]
+struct FilePlan {
+    /// Path of the file before being renamed, relative to the root of the export
+    path: PathBuf,
+    replacements: Vec<Replacement>,
+    /// Replacements that won't be done because they overlap with another one.
+    dropped_replacements: Vec<Replacement>,
+}
+
+/// RenamePlan lists everything the cleaning does, to be reviewed before it's applied.
+/// It's written as JSON. Its content is sorted, so that two plans can be compared.
+/// The files are kept sorted by path as they are added.
+#[derive(Debug, Serialize)]
+pub struct RenamePlan {
+    /// Files renames, then directories renames, in the order they are done.
+    renames: Vec<PathRename>,
+    files: Vec<FilePlan>,
+
+    #[serde(skip)]
+    root: PathBuf,
+}
+
+impl RenamePlan {
+    /// Plans the renames of all objects, whose files are in `root`.
+    pub fn new(root: &Path, all_objects: &Vec<&NotionObject>) -> Self {
+        let relative_rename = |(old, new): (PathBuf, PathBuf)| PathRename {
+            old: path_replacing::strip_root(&old, root),
+            new: path_replacing::strip_root(&new, root),
+        };
+
+        let mut files_renames = path_replacing::get_objects_files_renames(all_objects)
+            .into_iter()
+            .map(relative_rename)
+            .collect::<Vec<PathRename>>();
+        files_renames.sort_by(|left, right| left.old.cmp(&right.old));
+
+        // Directories must stay sorted from the deepest to the shallowest
+        let mut directories_renames = path_replacing::get_directories_renames(all_objects)
+            .into_iter()
+            .map(relative_rename)
+            .collect::<Vec<PathRename>>();
+        directories_renames.sort_by(|left, right| {
+            (Reverse(left.old.components().count()), &left.old)
+                .cmp(&(Reverse(right.old.components().count()), &right.old))
+        });
+
+        Self {
+            renames: files_renames.into_iter().chain(directories_renames).collect(),
+            files: vec![],
+            root: root.to_path_buf(),
+        }
+    }
+
+    /// Plans the replacements in the file at `path` (its path before being renamed).
+    pub fn add_file(&mut self, path: &Path, renamed_contents: &RenamedContents) {
+        let path = path_replacing::strip_root(path, &self.root);
+        let index = self.files.partition_point(|file| file.path < path);
+        self.files.insert(index, FilePlan {
+            path,
+            replacements: renamed_contents.replacements.clone(),
+            dropped_replacements: renamed_contents.dropped_replacements.clone(),
+        });
+    }
+
+    /// Writes the plan as JSON to `plan_path`.
+    pub fn write(&self, plan_path: &Path) -> io::Result<()> {
+        let file = fs::File::create(plan_path)?;
+        serde_json::to_writer_pretty(io::BufWriter::new(file), &self)?;
+        Ok(())
+    }
+}
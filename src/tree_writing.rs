@@ -0,0 +1,166 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use indicatif::ParallelProgressIterator;
+use rayon::prelude::*;
+
+use crate::{
+    constants::PROGRESS_BAR_STYLE,
+    content_replacing::{self, ReferencesMatcher, RenamedContents},
+    error::RewriteError,
+    export_source::ExportSource,
+    file_type::FileType,
+    notion_object::NotionObject,
+    path_replacing,
+};
+
+/// Writes the cleaned export to `output_dir`, reading the original from `source`, which is left untouched.
+/// All files are written at their final path, with their references renamed when they are readable, in parallel.
+/// Files absent from `all_files` (typically because they are ignored) are copied as they are.
+/// A file is never written over another one: if two files have the same final path, the second one is an error.
+/// Then `on_renamed` is called for each file whose references are renamed, or with problems to report, in the order of their paths.
+/// Its `RenamedContents` don't have their contents anymore, they were written.
+/// Files that can't be read or written are skipped, and their errors returned, in the order of their paths.
+pub fn write_cleaned_tree(
+    source: &impl ExportSource,
+    all_files: &Vec<&FileType>,
+    all_objects: &Vec<&NotionObject>,
+    matcher: &ReferencesMatcher,
+    output_dir: &Path,
+    mut on_renamed: impl FnMut(&PathBuf, RenamedContents),
+    is_test: bool,
+) -> Vec<RewriteError> {
+    let mut errors = vec![];
+    let renamed_paths = path_replacing::build_renamed_paths(all_objects);
+    let files_by_path: HashMap<&PathBuf, &FileType> = all_files
+        .iter()
+        .map(|ft| (ft.get_path(), *ft))
+        .collect();
+
+    let root = source.get_root().to_path_buf();
+    let final_path = |path: &Path| {
+        let new_path = path_replacing::get_final_path(path, &renamed_paths);
+        output_dir.join(new_path.strip_prefix(&root).unwrap()) // Should not panic, the root itself is never renamed
+    };
+
+    if !is_test {
+        // Directories are created first, so that empty ones are kept too. The root is not one of them.
+        // The directories pages are placed in, see `NameTemplate`, are created with them.
+        if let Err(source) = fs::create_dir_all(output_dir) {
+            errors.push(RewriteError::Write { path: output_dir.to_path_buf(), source });
+        }
+        for dir in source.get_directories().into_iter().chain(path_replacing::get_created_dirs(all_objects, &[])) {
+            let new_dir = final_path(&dir);
+            if let Err(source) = fs::create_dir_all(&new_dir) {
+                errors.push(RewriteError::Write { path: new_dir, source });
+            }
+        }
+    }
+
+    // Sorted, so that the results don't depend on the order of the files
+    let mut file_paths = source.get_file_paths();
+    file_paths.sort();
+
+    let results = file_paths
+        .par_iter()
+        .progress_with_style(PROGRESS_BAR_STYLE.clone())
+        .map(|path| {
+            let file = files_by_path.get(path).filter(|file| file.is_readable_type());
+            write_cleaned_file(source, path, file.is_some(), matcher, &final_path(path), is_test)
+        })
+        .collect::<Vec<_>>();
+
+    for (path, result) in file_paths.iter().zip(results) {
+        match result {
+            Ok(Some(renamed_contents)) => on_renamed(path, renamed_contents),
+            Ok(None) => {}
+            Err(e) => errors.push(e),
+        }
+    }
+
+    errors
+}
+
+/// Writes the file at `path` to `new_path`, with its references renamed if it `is_readable`, or copied as it is.
+/// Returns its renamed contents, without the contents themselves, if it has references to rename or problems to report.
+fn write_cleaned_file(
+    source: &impl ExportSource,
+    path: &Path,
+    is_readable: bool,
+    matcher: &ReferencesMatcher,
+    new_path: &Path,
+    is_test: bool,
+) -> Result<Option<RenamedContents>, RewriteError> {
+    if !is_readable {
+        if !is_test {
+            let mut new_file = File::create_new(new_path)
+                .map_err(|source| RewriteError::Write { path: new_path.to_path_buf(), source })?;
+            source
+                .copy_to(path, &mut new_file)
+                .map_err(|e| RewriteError::Read { path: path.to_path_buf(), source: e })?;
+        }
+        return Ok(None);
+    }
+
+    let old_contents = source
+        .read_to_string(path)
+        .map_err(|source| RewriteError::Read { path: path.to_path_buf(), source })?;
+    let mut renamed_contents = content_replacing::rename_refs_in_file_contents(&old_contents, path, matcher);
+    let new_contents = match renamed_contents.as_mut() {
+        Some(renamed_contents) => std::mem::take(&mut renamed_contents.contents),
+        None => old_contents,
+    };
+
+    if !is_test {
+        File::create_new(new_path)
+            .and_then(|mut new_file| new_file.write_all(new_contents.as_bytes()))
+            .map_err(|source| RewriteError::Write { path: new_path.to_path_buf(), source })?;
+    }
+
+    Ok(renamed_contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::export_source::ExportDirectory;
+
+    use super::*;
+
+    #[test]
+    fn reports_the_files_in_the_order_of_their_paths() {
+        let input = tempfile::tempdir().unwrap();
+        let output = tempfile::tempdir().unwrap();
+        let output_dir = output.path().join("cleaned");
+        // Pages linking to a Notion page that is not part of the export, and files that are not UTF-8
+        for i in (0..20).rev() {
+            fs::write(input.path().join(format!("Page {:02}.md", i)), "[Out](https://www.notion.so/Out-11111111111111111111111111111111)").unwrap();
+            fs::write(input.path().join(format!("Binary {:02}.md", i)), [0xff, 0xfe]).unwrap();
+        }
+        let source = ExportDirectory::new(input.path().to_path_buf());
+        let files = source.get_file_paths().into_iter().map(|path| FileType::try_from(path).unwrap()).collect::<Vec<FileType>>();
+        let matcher = ReferencesMatcher::new(&vec![]);
+
+        let mut renamed_paths = vec![];
+        let on_renamed = |path: &PathBuf, renamed_contents: RenamedContents| {
+            assert_eq!(renamed_contents.errors.len(), 1);
+            renamed_paths.push(path.clone());
+        };
+        let errors = write_cleaned_tree(&source, &files.iter().collect(), &vec![], &matcher, &output_dir, on_renamed, false);
+
+        let expected_paths = |prefix: &str| (0..20).map(|i| input.path().join(format!("{} {:02}.md", prefix, i))).collect::<Vec<PathBuf>>();
+        assert_eq!(renamed_paths, expected_paths("Page"));
+        let error_paths = errors
+            .iter()
+            .map(|error| match error {
+                RewriteError::Read { path, .. } => path.clone(),
+                error => panic!("unexpected error {}", error),
+            })
+            .collect::<Vec<PathBuf>>();
+        assert_eq!(error_paths, expected_paths("Binary"));
+        assert!(output_dir.join("Page 00.md").exists());
+    }
+}
@@ -0,0 +1,415 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    constants::UNDO_RECORD_FILE_NAME,
+    content_replacing::{self, Replacement},
+    journal::Journal,
+    notion_object::{self, NotionObject, NotionObjectInfo},
+    path_replacing,
+};
+
+/// A path before and after being renamed. Both are relative to the root of the export.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PathRename {
+    pub old: PathBuf,
+    pub new: PathBuf,
+}
+
+/// How a page or a database has been renamed.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ObjectRecord {
+    pub uuid: String,
+    pub old_name: String,
+    pub new_name: String,
+    pub path: PathRename,
+    pub csv_all_path: Option<PathRename>,
+    pub html_path: Option<PathRename>,
+    pub dir_path: Option<PathRename>,
+}
+
+/// A page or a database that was already cleaned by a previous run, and left as it is.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CleanedObjectRecord {
+    pub uuid: String,
+    pub old_name: String,
+    pub new_name: String,
+    /// Current path, relative to the root of the export
+    pub path: PathBuf,
+    pub dir_path: Option<PathBuf>,
+}
+
+/// The references replaced in a file.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FileRecord {
+    /// Path of the file in the cleaned export, relative to its root
+    pub path: PathBuf,
+    pub replacements: Vec<Replacement>,
+}
+
+/// UndoRecord stores everything the cleaning did, so that it can be undone.
+/// It's written at the root of the cleaned export.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UndoRecord {
+    pub objects: Vec<ObjectRecord>,
+    pub files: Vec<FileRecord>,
+    /// Objects cleaned by previous runs. They are not restored, but kept to recognize them in the next runs.
+    #[serde(default)]
+    pub cleaned_objects: Vec<CleanedObjectRecord>,
+    /// Files created by the cleaning, like the placeholder page of `ExternalLinks::Placeholder`. Relative to the root of the export.
+    #[serde(default)]
+    pub created_files: Vec<PathBuf>,
+    /// Directories created by the cleaning to place pages in, see `NameTemplate`, from the shallowest to the deepest.
+    /// Relative to the root of the export, with the names their parents had before the cleaning.
+    #[serde(default)]
+    pub created_dirs: Vec<PathBuf>,
+    /// The record of the previous run on the same export, if any. It's undone after this one, to get back the original export.
+    #[serde(default)]
+    pub previous: Option<Box<UndoRecord>>,
+
+    /// Renamed paths by old path, to find where the files end up.
+    #[serde(skip)]
+    renamed_paths: HashMap<PathBuf, PathBuf>,
+    /// Root of the export that is cleaned.
+    #[serde(skip)]
+    root: PathBuf,
+}
+
+impl UndoRecord {
+    /// Records the renames of all objects, whose files are in `root`.
+    pub fn new(root: &Path, all_objects: &Vec<&NotionObject>) -> Self {
+        let relative_rename = |(old, new): (PathBuf, PathBuf)| PathRename {
+            old: path_replacing::strip_root(&old, root),
+            new: path_replacing::strip_root(&new, root),
+        };
+
+        let objects = all_objects
+            .iter()
+            .filter_map(|obj| match obj {
+                NotionObject::Page(info, ..) | NotionObject::Database(info, ..) => Some((obj, info)),
+                _ => None,
+            })
+            // Objects without a new name are not renamed
+            .filter_map(|(obj, info): (&&NotionObject, &NotionObjectInfo)| Some(ObjectRecord {
+                uuid: info.uuid.clone(),
+                old_name: info.old_name.clone(),
+                new_name: info.new_name.clone()?,
+                path: relative_rename(obj.get_old_and_new_paths()?),
+                csv_all_path: obj.get_old_and_new_csv_all_paths().map(relative_rename),
+                html_path: obj.get_old_and_new_html_paths().map(relative_rename),
+                dir_path: obj.get_old_and_new_dir_paths().map(relative_rename),
+            }))
+            .collect();
+
+        let cleaned_objects = all_objects
+            .iter()
+            .filter_map(|obj| match obj {
+                NotionObject::Cleaned(info, ..) => Some(CleanedObjectRecord {
+                    uuid: info.uuid.clone(),
+                    old_name: info.old_name.clone(),
+                    new_name: info.new_name.clone().unwrap_or_default(),
+                    path: path_replacing::strip_root(&info.path, root),
+                    dir_path: info.dir_path.as_ref().map(|dir_path| path_replacing::strip_root(dir_path, root)),
+                }),
+                _ => None,
+            })
+            .collect();
+
+        Self {
+            objects,
+            files: vec![],
+            cleaned_objects,
+            created_files: vec![],
+            created_dirs: vec![],
+            previous: None,
+            renamed_paths: path_replacing::build_renamed_paths(all_objects),
+            root: root.to_path_buf(),
+        }
+    }
+
+    /// Records the replacements done in the file at `path` (its path before being renamed).
+    pub fn add_file(&mut self, path: &Path, replacements: Vec<Replacement>) {
+        if replacements.is_empty() {
+            return;
+        }
+        let final_path = path_replacing::get_final_path(path, &self.renamed_paths);
+        self.files.push(FileRecord {
+            path: path_replacing::strip_root(&final_path, &self.root),
+            replacements,
+        });
+    }
+
+    /// Records the creation of the file at `path`, which is never renamed.
+    pub fn add_created_file(&mut self, path: &Path) {
+        self.created_files.push(path_replacing::strip_root(path, &self.root));
+    }
+
+    /// Records the creation of the directory at `path`, with the names its parents had before the cleaning.
+    pub fn add_created_dir(&mut self, path: &Path) {
+        self.created_dirs.push(path_replacing::strip_root(path, &self.root));
+    }
+
+    /// Writes the record at the root of the cleaned export.
+    pub fn write(&self, output_root: &Path) -> io::Result<()> {
+        let file = fs::File::create(output_root.join(UNDO_RECORD_FILE_NAME))?;
+        serde_json::to_writer(io::BufWriter::new(file), self)?;
+        Ok(())
+    }
+
+    /// Reads the record at the root of a cleaned export.
+    pub fn read(root: &Path) -> io::Result<Self> {
+        let file = fs::File::open(root.join(UNDO_RECORD_FILE_NAME))?;
+        let mut record: Self = serde_json::from_reader(io::BufReader::new(file))?;
+        record.set_root(root);
+        Ok(record)
+    }
+
+    /// Sets the root of this record and of the previous ones, which are not serialized.
+    fn set_root(&mut self, root: &Path) {
+        self.root = root.to_path_buf();
+        if let Some(previous) = self.previous.as_mut() {
+            previous.set_root(root);
+        }
+    }
+
+    /// Returns the infos of all objects cleaned by the run that wrote this record, or by the previous ones.
+    /// Their paths are their current ones, and their new names are set.
+    pub fn get_cleaned_objects(&self) -> Vec<NotionObjectInfo> {
+        // Each object has only its own name changed, its final path depends on the renames of its parents
+        let renamed_paths: HashMap<PathBuf, PathBuf> = self
+            .objects
+            .iter()
+            .flat_map(|obj| [Some(&obj.path), obj.csv_all_path.as_ref(), obj.html_path.as_ref(), obj.dir_path.as_ref()])
+            .flatten()
+            .map(|rename| (self.root.join(&rename.old), self.root.join(&rename.new)))
+            .collect();
+
+        let objects = self.objects.iter().map(|obj| {
+            let final_path = |rename: &PathRename| path_replacing::get_final_path(&self.root.join(&rename.old), &renamed_paths);
+            (&obj.uuid, &obj.old_name, &obj.new_name, final_path(&obj.path), obj.dir_path.as_ref().map(final_path))
+        });
+        let previously_cleaned_objects = self.cleaned_objects.iter().map(|obj| {
+            (&obj.uuid, &obj.old_name, &obj.new_name, self.root.join(&obj.path), obj.dir_path.as_ref().map(|dir_path| self.root.join(dir_path)))
+        });
+
+        objects
+            .chain(previously_cleaned_objects)
+            .filter_map(|(uuid, old_name, new_name, path, dir_path)| {
+                let (name, _) = notion_object::split_name_and_uuid(old_name)?;
+                Some(NotionObjectInfo {
+                    old_name: old_name.clone(),
+                    new_name: Some(new_name.clone()),
+                    ..NotionObjectInfo::new(path, name, uuid.clone(), dir_path)
+                })
+            })
+            .collect()
+    }
+
+    /// Did the cleaning do nothing? The previous runs are not considered.
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty() && self.files.is_empty() && self.created_files.is_empty()
+    }
+
+    pub fn exists(root: &Path) -> bool {
+        root.join(UNDO_RECORD_FILE_NAME).exists()
+    }
+
+    /// Stages in `journal` everything needed to get back the original export.
+    /// Without a journal, only checks that the contents can be restored.
+    /// Returns the files whose contents can't be restored, because they have been modified since they were cleaned.
+    /// The contents are restored first, while the files still have their new paths.
+    /// Then the renames are undone in the opposite order they were done:
+    /// directories from the shallowest to the deepest, then the files.
+    /// Finally, the files created by the cleaning are removed, then the directories it created, from the deepest, if they are empty.
+    pub fn stage_undo(&self, mut journal: Option<&mut Journal>) -> io::Result<Vec<PathBuf>> {
+        let mut modified_files = vec![];
+        for file in self.files.iter() {
+            let path = self.root.join(&file.path);
+            let new_contents = fs::read_to_string(&path)?;
+            match content_replacing::undo_replacements(&new_contents, &file.replacements) {
+                Some(old_contents) => {
+                    if let Some(journal) = journal.as_deref_mut() {
+                        journal.stage_write(&path, old_contents)?;
+                    }
+                }
+                None => modified_files.push(path),
+            }
+        }
+
+        let mut dir_renames = self
+            .objects
+            .iter()
+            .filter_map(|obj| obj.dir_path.as_ref())
+            .collect::<Vec<&PathRename>>();
+        dir_renames.sort_by_key(|rename| rename.old.components().count());
+
+        let file_renames = self.objects.iter().flat_map(|obj| {
+            [Some(&obj.path), obj.csv_all_path.as_ref(), obj.html_path.as_ref()]
+                .into_iter()
+                .flatten()
+        });
+
+        if let Some(journal) = journal {
+            for rename in dir_renames.into_iter().chain(file_renames) {
+                journal.stage_rename(&self.root.join(&rename.new), &self.root.join(&rename.old));
+            }
+            for created_file in self.created_files.iter() {
+                journal.stage_remove(&self.root.join(created_file));
+            }
+            for created_dir in self.created_dirs.iter().rev() {
+                journal.stage_remove_dir(&self.root.join(created_dir));
+            }
+        }
+
+        Ok(modified_files)
+    }
+
+    /// Removes the record from the root of the export.
+    pub fn remove(&self) -> io::Result<()> {
+        fs::remove_file(self.root.join(UNDO_RECORD_FILE_NAME))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+    use walkdir::WalkDir;
+
+    use crate::cleaner::Cleaner;
+
+    use super::*;
+
+    const HOME_UUID: &str = "11111111111111111111111111111111";
+    const CHILD_UUID: &str = "22222222222222222222222222222222";
+
+    /// Creates an export with a page and its child page, linking to each other.
+    fn create_export() -> TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join(format!("Home {}", HOME_UUID))).unwrap();
+        fs::write(
+            dir.path().join(format!("Home {}.md", HOME_UUID)),
+            format!("# Home\n\n[Child](Home%20{0}/Child%20{1}.md)\n", HOME_UUID, CHILD_UUID),
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join(format!("Home {}", HOME_UUID)).join(format!("Child {}.md", CHILD_UUID)),
+            format!("# Child\n\n[Home](../Home%20{}.md)\n", HOME_UUID),
+        )
+        .unwrap();
+        dir
+    }
+
+    /// Returns the paths of all entries in `root`, relative to it, with the contents of the files.
+    fn read_tree(root: &Path) -> Vec<(String, Option<String>)> {
+        WalkDir::new(root)
+            .min_depth(1)
+            .sort_by_file_name()
+            .into_iter()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                let path = entry.path().strip_prefix(root).unwrap().to_string_lossy().replace('\\', "/");
+                let contents = entry.file_type().is_file().then(|| fs::read_to_string(entry.path()).unwrap());
+                (path, contents)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn undo_restores_the_original_export() {
+        let dir = create_export();
+        let original_tree = read_tree(dir.path());
+
+        Cleaner::new(dir.path()).run().unwrap();
+        assert!(UndoRecord::exists(dir.path()));
+        assert_eq!(fs::read_to_string(dir.path().join("Home.md")).unwrap(), "# Home\n\n[Child](Home/Child.md)\n");
+
+        let report = Cleaner::new(dir.path()).undo().unwrap();
+        assert!(report.modified_files.is_empty());
+        assert_eq!(read_tree(dir.path()), original_tree);
+    }
+
+    #[test]
+    fn undo_removes_the_directories_pages_were_placed_in() {
+        let dir = create_export();
+        let original_tree = read_tree(dir.path());
+
+        let report = Cleaner::new(dir.path()).name_template("{parent}/{name}".parse().unwrap()).verify(true).run().unwrap();
+        assert!(!report.verify.unwrap().has_problems());
+        // The child is placed in a directory named after its parent, in the directory of its parent
+        assert_eq!(fs::read_to_string(dir.path().join("Home.md")).unwrap(), "# Home\n\n[Child](Home/Home/Child.md)\n");
+        assert_eq!(fs::read_to_string(dir.path().join("Home/Home/Child.md")).unwrap(), "# Child\n\n[Home](../../Home.md)\n");
+        assert_eq!(UndoRecord::read(dir.path()).unwrap().created_dirs, [PathBuf::from(format!("Home {}/Home", HOME_UUID))]);
+
+        Cleaner::new(dir.path()).undo().unwrap();
+        assert_eq!(read_tree(dir.path()), original_tree);
+    }
+
+    #[test]
+    fn undo_reports_the_files_modified_since_the_cleaning() {
+        let dir = create_export();
+        Cleaner::new(dir.path()).run().unwrap();
+        fs::write(dir.path().join("Home.md"), "# Home, rewritten\n").unwrap();
+
+        let report = Cleaner::new(dir.path()).undo().unwrap();
+        assert_eq!(report.modified_files, vec![dir.path().join("Home.md")]);
+        // The file gets its name back, but keeps its contents
+        assert_eq!(fs::read_to_string(dir.path().join(format!("Home {}.md", HOME_UUID))).unwrap(), "# Home, rewritten\n");
+    }
+
+    #[test]
+    fn finds_the_current_paths_of_the_cleaned_objects() {
+        let dir = create_export();
+        Cleaner::new(dir.path()).run().unwrap();
+
+        let record = UndoRecord::read(dir.path()).unwrap();
+        let mut cleaned = record
+            .get_cleaned_objects()
+            .into_iter()
+            .map(|info| (info.name, info.new_name.unwrap(), info.path, info.dir_path))
+            .collect::<Vec<_>>();
+        cleaned.sort();
+        assert_eq!(
+            cleaned,
+            vec![
+                ("Child".to_string(), "Child".to_string(), dir.path().join("Home").join("Child.md"), None),
+                ("Home".to_string(), "Home".to_string(), dir.path().join("Home.md"), Some(dir.path().join("Home"))),
+            ]
+        );
+    }
+
+    #[test]
+    fn undo_restores_the_original_export_after_several_runs() {
+        let dir = create_export();
+        let mut original_tree = read_tree(dir.path());
+        Cleaner::new(dir.path()).run().unwrap();
+
+        // A page added to the cleaned export, linking to a page cleaned by the first run
+        let notes_name = "Notes 33333333333333333333333333333333.md";
+        let notes_contents = format!("# Notes\n\n[Home](../Home%20{}.md)\n", HOME_UUID);
+        fs::write(dir.path().join("Home").join(notes_name), &notes_contents).unwrap();
+
+        let report = Cleaner::new(dir.path()).run().unwrap();
+        // Pages cleaned by the first run are not renamed again
+        assert_eq!(report.cleaned_objects_count, 2);
+        assert!(dir.path().join("Home.md").exists());
+        assert_eq!(fs::read_to_string(dir.path().join("Home").join("Notes.md")).unwrap(), "# Notes\n\n[Home](../Home.md)\n");
+
+        let record = UndoRecord::read(dir.path()).unwrap();
+        assert_eq!(record.objects.len(), 1);
+        assert_eq!(record.cleaned_objects.len(), 2);
+        assert!(record.previous.is_some());
+
+        // Both runs are undone: the added page ends up in the original directory of its parent
+        Cleaner::new(dir.path()).undo().unwrap();
+        original_tree.push((format!("Home {}/{}", HOME_UUID, notes_name), Some(notes_contents)));
+        original_tree.sort();
+        let mut undone_tree = read_tree(dir.path());
+        undone_tree.sort();
+        assert_eq!(undone_tree, original_tree);
+    }
+}
@@ -76,3 +76,30 @@ fn to_hex_digit(digit: u8) -> u8 {
     }
 }
 
+
+/// Decodes the percent-encoded bytes of `data`. Malformed sequences are left as they are.
+/// Returns None if the decoded bytes are not UTF-8.
+pub fn decode(data: &str) -> Option<Cow<'_, str>> {
+    if !data.contains('%') {
+        return Some(Cow::Borrowed(data));
+    }
+
+    let bytes = data.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let hex_value = |b: u8| (b as char).to_digit(16).map(|d| d as u8);
+        match (bytes[index], bytes.get(index + 1).copied().and_then(hex_value), bytes.get(index + 2).copied().and_then(hex_value)) {
+            (b'%', Some(high), Some(low)) => {
+                decoded.push(high << 4 | low);
+                index += 3;
+            }
+            (byte, ..) => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+
+    String::from_utf8(decoded).ok().map(Cow::Owned)
+}